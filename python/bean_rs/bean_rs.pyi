@@ -1,8 +1,38 @@
+from decimal import Decimal
+
 from bean_rs._bean_rs import Directive
 
+class Comments:
+    leading: list[str]
+    trailing: str | None
+    following: list[str]
+
+class Amount:
+    number: Decimal
+    ccy: str
+
+class Posting:
+    account: str
+    amount: Amount | None
+    comments: Comments
+
+class Transaction:
+    payee: str | None
+    narration: str
+    tags: list[str]
+    links: list[str]
+    postings: list[Posting]
+    comments: Comments
+    section: list[str]
+
 class Options:
     title: str
     operating_currency: str
+    name_assets: str
+    name_liabilities: str
+    name_equity: str
+    name_income: str
+    name_expenses: str
 
 class BeanError(Exception):
     pass
@@ -1,3 +1,5 @@
+from decimal import Decimal
+
 from bean_rs import load
 from bean_rs._bean_rs import Transaction
 
@@ -7,7 +9,13 @@ def test_load() -> None:
     assert ledger.opts.operating_currency == "GBP"
     for d in ledger.dirs:
         match d[0]:  # each element in enum is single-element tuple
-            case Transaction(payee='"Shop"', narration=narration):
-                assert narration == '"More food"'
+            case Transaction(payee="Shop", narration=narration, section=section):
+                assert narration == "More food"
+                assert section == ["Example beancount file", "Transactions"]
+                assert d[0].comments.leading == []
+                posting = d[0].postings[0]
+                assert posting.account == "Assets:Bank"
+                assert posting.amount.number == Decimal("-40.00")
+                assert posting.comments.trailing is None
             case _:
                 pass
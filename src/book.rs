@@ -1,16 +1,52 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use log::debug;
 use rust_decimal::Decimal;
 
 use crate::{
-    data::{AccBal, AccStatuses, Account, Amount, CcyBal, Directive, Pad, Posting, Transaction},
+    data::{
+        AccBal, AccStatuses, Account, Amount, CcyBal, Comments, Cost, Directive, Pad, Posting,
+        Transaction,
+    },
     error::{BeanError, ErrorType},
+    inventory::{self, Inventory, Position},
     loader,
 };
 
+/// Fill in the cost of each reduction posted with an empty `{}` from the lots
+/// held in its account, oldest first, with a Posting for each lot it reduces
+fn book_reductions(tx: &mut Transaction, lots: &BTreeMap<Account, Inventory>) {
+    let mut postings: Vec<Posting> = Vec::new();
+    for p in tx.postings.drain(..) {
+        let empty_cost = matches!(&p.cost, Some(Cost { amount: None }));
+        let (Some(units), Some(inv), true) = (&p.amount, lots.get(&p.account), empty_cost) else {
+            postings.push(p);
+            continue;
+        };
+        let (reductions, remaining) = inv.reductions(units);
+        for (i, reduction) in reductions.into_iter().enumerate() {
+            let mut booked = p.clone();
+            if i > 0 {
+                booked.comments = Comments::default();
+            }
+            booked.amount = Some(reduction.units);
+            booked.cost = Some(Cost {
+                amount: reduction.cost,
+            });
+            postings.push(booked);
+        }
+        if !remaining.is_zero() {
+            let mut rest = p.clone();
+            rest.amount = Some(Amount::new(remaining, units.ccy.clone()));
+            postings.push(rest);
+        }
+    }
+    tx.postings = postings;
+}
+
 /// Checks postings with no `Amount` and calculates the values
-/// needed for the Transaction to balance.
+/// needed for the Transaction to balance, using the weight of
+/// each Posting (see `inventory::weight`).
 ///
 /// If a Transaction is unbalanced but it has a Posting with no Amount,
 /// then the account from that Posting is used to balance the transaction.
@@ -19,7 +55,7 @@ use crate::{
 fn complete_postings(tx: &mut Transaction) -> Vec<BeanError> {
     debug!("balancing {tx:?}");
 
-    let mut ccy_bals: CcyBal = HashMap::new();
+    let mut ccy_bals: CcyBal = BTreeMap::new();
 
     // (Revisit this) Instead of appending to the original postings,
     // create a brand new Vec. Makes the logic of
@@ -48,18 +84,22 @@ fn complete_postings(tx: &mut Transaction) -> Vec<BeanError> {
                 empty_posting_index = i;
                 found_empty_posting = true;
             }
-            Some(amount) => {
-                *ccy_bals.entry(amount.ccy.clone()).or_default() += amount.number;
+            Some(_) => {
+                if let Some(weight) = inventory::weight(p) {
+                    *ccy_bals.entry(weight.ccy).or_default() += weight.number;
+                }
                 postings.push(p.clone())
             }
         }
     }
 
     if found_empty_posting {
-        let account = &tx.postings[empty_posting_index].account;
+        let empty = &tx.postings[empty_posting_index];
         for (ccy, number) in &ccy_bals {
-            let p = Posting::new(account.clone(), -number, ccy.clone());
-            postings.push(p.clone())
+            // Keep the metadata etc from the original Posting
+            let mut p = empty.clone();
+            p.amount = Some(Amount::new(-number, ccy.clone()));
+            postings.push(p)
         }
     }
 
@@ -67,17 +107,17 @@ fn complete_postings(tx: &mut Transaction) -> Vec<BeanError> {
     errs
 }
 
-/// Checks that Transaction balances in all currencies to 0
+/// Checks that the weights of the Transaction's postings sum to 0 in all currencies
 /// MUST be run after `complete_postings`
 fn check_transaction(tx: &Transaction) -> Vec<BeanError> {
     let mut errs: Vec<BeanError> = Vec::new();
-    let mut ccy_bals: CcyBal = HashMap::new();
+    let mut ccy_bals: CcyBal = BTreeMap::new();
     for p in tx.postings.iter() {
-        match &p.amount {
+        match inventory::weight(p) {
             // TODO use RawTransaction/Transaction and RawPosting/Posting to make impossible
             None => panic!("Found empty postings after they should have been replaced, abort."),
-            Some(amount) => {
-                *ccy_bals.entry(amount.ccy.clone()).or_default() += amount.number;
+            Some(weight) => {
+                *ccy_bals.entry(weight.ccy).or_default() += weight.number;
             }
         }
     }
@@ -97,14 +137,22 @@ fn check_transaction(tx: &Transaction) -> Vec<BeanError> {
     errs
 }
 
-/// Complete postings as needed and check balances
+/// Book reductions against lots, complete postings as needed and check balances
 /// Directives MUST be sorted appropriately before calling this
 pub fn balance_transactions(directives: &mut [Directive]) -> Vec<BeanError> {
     let mut errs: Vec<BeanError> = Vec::new();
+    let mut lots: BTreeMap<Account, Inventory> = BTreeMap::new();
     for d in directives.iter_mut() {
         if let Directive::Transaction(tx) = d {
+            book_reductions(tx, &lots);
             errs.extend(complete_postings(tx));
             errs.extend(check_transaction(tx));
+            for p in &tx.postings {
+                let held_at_cost = p.cost.as_ref().is_some_and(|c| c.amount.is_some());
+                if let (true, Some(pos)) = (held_at_cost, Position::from_posting(p)) {
+                    lots.entry(p.account.clone()).or_default().add(&pos);
+                }
+            }
         }
     }
     errs
@@ -170,8 +218,8 @@ fn proc_tx(tx: &Transaction, bals: &mut AccBal, accs: &mut AccStatuses, errs: &m
 
 /// Get balances for all accounts in all currencies
 pub fn get_balances(dirs: &mut Vec<Directive>) -> (AccBal, Vec<BeanError>) {
-    let mut bals: AccBal = HashMap::new();
-    let mut accs: AccStatuses = HashMap::new();
+    let mut bals: AccBal = BTreeMap::new();
+    let mut accs: AccStatuses = BTreeMap::new();
     let mut errs: Vec<BeanError> = Vec::new();
     let mut pads: HashMap<Account, (bool, Pad)> = HashMap::new();
     let mut ptxs: Vec<Directive> = Vec::new();
@@ -287,11 +335,58 @@ pub fn get_balances(dirs: &mut Vec<Directive>) -> (AccBal, Vec<BeanError>) {
     (bals, errs)
 }
 
+/// Add the postings of a Transaction to `bals`, without any checks
+fn add_postings(tx: &Transaction, bals: &mut AccBal) {
+    for p in &tx.postings {
+        if let Some(amount) = &p.amount {
+            let entry = bals.entry(p.account.clone()).or_default();
+            *entry.entry(amount.ccy.clone()).or_default() += amount.number;
+        }
+    }
+}
+
+/// Balances from the postings of every Transaction, without any checks,
+/// eg for Directives that have been filtered
+pub fn sum_postings(dirs: &[Directive]) -> AccBal {
+    let mut bals: AccBal = BTreeMap::new();
+    for d in dirs {
+        if let Directive::Transaction(tx) = d {
+            add_postings(tx, &mut bals);
+        }
+    }
+    bals
+}
+
+/// Balances from only the Transactions under the heading `heading` (at any level)
+pub fn section_balances(dirs: &[Directive], heading: &str) -> AccBal {
+    let mut bals: AccBal = BTreeMap::new();
+    for d in dirs {
+        if let Directive::Transaction(tx) = d {
+            if d.in_section(heading) {
+                add_postings(tx, &mut bals);
+            }
+        }
+    }
+    bals
+}
+
+/// Balances from the Transactions in each section, keyed by the full heading path
+pub fn balances_by_section(dirs: &[Directive]) -> BTreeMap<Vec<String>, AccBal> {
+    let mut sections: BTreeMap<Vec<String>, AccBal> = BTreeMap::new();
+    for d in dirs {
+        if let Directive::Transaction(tx) = d {
+            let bals = sections.entry(tx.section.clone()).or_default();
+            add_postings(tx, bals);
+        }
+    }
+    sections
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::NaiveDate;
 
-    use crate::data::{DebugLine, DATE_FMT};
+    use crate::data::{Comments, DebugLine, DATE_FMT};
 
     use super::*;
 
@@ -300,6 +395,10 @@ mod tests {
         let p1 = Posting {
             account: "Assets:Bank".to_string(),
             amount: None,
+            cost: None,
+            price: None,
+            meta: vec![],
+            comments: Comments::default(),
             debug: None,
         };
         let p2 = p1.clone();
@@ -309,10 +408,12 @@ mod tests {
             ty: "*".to_string(),
             payee: None,
             narration: "".to_string(),
-            tag: None,
-            link: None,
+            tags: vec![],
+            links: vec![],
             postings: vec![p1, p2],
             meta: vec![],
+            comments: Comments::default(),
+            section: vec![],
             debug: DebugLine { line: 0 },
         };
         let errs = complete_postings(&mut tx);
@@ -324,11 +425,19 @@ mod tests {
         let p1 = Posting {
             account: "Assets:Bank".to_string(),
             amount: Some(Amount::new(Decimal::new(100, 1), "USD".to_string())),
+            cost: None,
+            price: None,
+            meta: vec![],
+            comments: Comments::default(),
             debug: None,
         };
         let p2 = Posting {
             account: "Income:Job".to_string(),
             amount: Some(Amount::new(Decimal::new(-100, 1), "USD".to_string())),
+            cost: None,
+            price: None,
+            meta: vec![],
+            comments: Comments::default(),
             debug: None,
         };
         let date = NaiveDate::parse_from_str("2023-01-01", DATE_FMT).unwrap();
@@ -337,14 +446,16 @@ mod tests {
             ty: "*".to_string(),
             payee: None,
             narration: "".to_string(),
-            tag: None,
-            link: None,
+            tags: vec![],
+            links: vec![],
             postings: vec![p1, p2],
             meta: vec![],
+            comments: Comments::default(),
+            section: vec![],
             debug: DebugLine { line: 0 },
         };
-        let mut bals: AccBal = HashMap::new();
-        let mut accs: AccStatuses = HashMap::new();
+        let mut bals: AccBal = BTreeMap::new();
+        let mut accs: AccStatuses = BTreeMap::new();
         accs.insert("Assets:Bank".to_string(), (true, vec!["FOO".to_string()]));
         accs.insert("Income:Job".to_string(), (true, vec!["FOO".to_string()]));
         let mut errs: Vec<BeanError> = vec![];
@@ -357,11 +468,19 @@ mod tests {
         let p1 = Posting {
             account: "Assets:Bank".to_string(),
             amount: Some(Amount::new(Decimal::new(100, 1), "USD".to_string())),
+            cost: None,
+            price: None,
+            meta: vec![],
+            comments: Comments::default(),
             debug: None,
         };
         let p2 = Posting {
             account: "Income:Job".to_string(),
             amount: Some(Amount::new(Decimal::new(-100, 1), "USD".to_string())),
+            cost: None,
+            price: None,
+            meta: vec![],
+            comments: Comments::default(),
             debug: None,
         };
         let date = NaiveDate::parse_from_str("2023-01-01", DATE_FMT).unwrap();
@@ -370,18 +489,90 @@ mod tests {
             ty: "*".to_string(),
             payee: None,
             narration: "".to_string(),
-            tag: None,
-            link: None,
+            tags: vec![],
+            links: vec![],
             postings: vec![p1, p2],
             meta: vec![],
+            comments: Comments::default(),
+            section: vec![],
             debug: DebugLine { line: 0 },
         };
-        let mut bals: AccBal = HashMap::new();
-        let mut accs: AccStatuses = HashMap::new();
+        let mut bals: AccBal = BTreeMap::new();
+        let mut accs: AccStatuses = BTreeMap::new();
         accs.insert("Assets:Bank".to_string(), (false, vec!["USD".to_string()]));
         accs.insert("Income:Job".to_string(), (false, vec!["USD".to_string()]));
         let mut errs: Vec<BeanError> = vec![];
         proc_tx(&tx, &mut bals, &mut accs, &mut errs);
         assert!(errs.first().unwrap().ty == ErrorType::ClosedAccount);
     }
+
+    #[test]
+    fn test_balance_by_weight() {
+        let text = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Broker
+2023-01-01 open Income:Gains
+2023-01-10 * "Buy"
+  Assets:Broker  10 GOOG {50 GBP}
+  Assets:Broker  10 GOOG {60 GBP}
+  Assets:Bank
+2023-02-01 * "Sell"
+  Assets:Broker  -15 GOOG {} @ 70 GBP
+  Assets:Bank    1050 GBP
+  Income:Gains
+2023-02-02 * "Exchange"
+  Assets:Bank  100 USD @ 0.80 GBP
+  Assets:Bank
+2023-02-03 * "Unbalanced"
+  Assets:Bank  100 USD @ 0.80 GBP
+  Assets:Bank  -100 GBP
+"#;
+        let mut ledger = crate::load(text.to_string());
+        let errs = balance_transactions(&mut ledger.dirs);
+        assert!(errs.len() == 1);
+        assert!(errs[0].ty == ErrorType::UnbalancedTransaction);
+
+        let (bals, _) = get_balances(&mut ledger.dirs);
+        assert!(bals["Assets:Bank"]["GBP"] == Decimal::new(-230, 0));
+        assert!(!bals["Assets:Bank"].contains_key("GOOG"));
+        // The sale is booked against the lot at 50 GBP, then 5 from the lot at 60 GBP
+        assert!(bals["Income:Gains"]["GBP"] == Decimal::new(-250, 0));
+        let Directive::Transaction(sell) = &ledger.dirs[4] else {
+            panic!("Expected a Transaction");
+        };
+        let costs: Vec<String> = sell.postings[..2]
+            .iter()
+            .map(|p| Position::from_posting(p).unwrap().to_string())
+            .collect();
+        assert!(costs == ["-10 GOOG {50 GBP}", "-5 GOOG {60 GBP}"]);
+    }
+
+    #[test]
+    fn test_section_balances() {
+        let text = r#"
+* Home
+2023-01-01 open Assets:Bank
+2023-01-01 open Expenses:Rent
+2023-01-01 open Income:Rent
+** Rental flat
+2023-01-02 * "Tenant"
+  Assets:Bank  500 GBP
+  Income:Rent
+** Bills
+2023-01-03 * "Landlord"
+  Expenses:Rent  200 GBP
+  Assets:Bank
+"#;
+        let mut ledger = crate::load(text.to_string());
+        get_balances(&mut ledger.dirs);
+        let bals = section_balances(&ledger.dirs, "Rental flat");
+        assert!(bals["Assets:Bank"]["GBP"] == Decimal::new(500, 0));
+        assert!(!bals.contains_key("Expenses:Rent"));
+        let bals = section_balances(&ledger.dirs, "Home");
+        assert!(bals["Assets:Bank"]["GBP"] == Decimal::new(300, 0));
+
+        let sections = balances_by_section(&ledger.dirs);
+        let keys: Vec<&Vec<String>> = sections.keys().collect();
+        assert!(keys == [&vec!["Home", "Bills"], &vec!["Home", "Rental flat"]]);
+    }
 }
@@ -0,0 +1,461 @@
+//! A lossless concrete syntax tree
+//!
+//! Every byte of the input belongs to exactly one Token or leaf Node,
+//! so the original text can always be rebuilt exactly.
+//! Text that the grammar doesn't keep (whitespace, comments, headings,
+//! keywords and punctuation) is stored as Tokens, and the trivia between
+//! top-level entries is attached to the entry that it belongs to.
+//! The Directives in a Ledger are derived from the Nodes (see `loader::consume`),
+//! so a changed Node is reflected in what gets loaded.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use pest::iterators::Pair;
+
+use crate::grammar::Rule;
+use crate::loader;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenKind {
+    /// Spaces and tabs
+    Whitespace,
+    /// `\n` or `\r\n`
+    Newline,
+    /// A `;` comment, up to the end of the line
+    Comment,
+    /// An org-mode style `*` heading line between entries
+    Heading,
+    /// Anything else that isn't part of a Node, eg keywords and punctuation
+    Text,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token<'i> {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+    pub text: &'i str,
+}
+
+impl<'i> Token<'i> {
+    pub fn is_trivia(&self) -> bool {
+        self.kind != TokenKind::Text
+    }
+
+    /// The depth and title of a Heading, eg `** Rental flat` is `(2, "Rental flat")`
+    pub fn heading(&self) -> Option<(usize, &'i str)> {
+        if self.kind != TokenKind::Heading {
+            return None;
+        }
+        let title = self.text.trim_start_matches('*');
+        let depth = self.text.len() - title.len();
+        Some((depth, title.trim()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Element<'i> {
+    Node(Node<'i>),
+    Token(Token<'i>),
+}
+
+/// A Node for each (non-silent) grammar Rule that matched
+/// Nodes with no children are leaves and cover their text themselves
+#[derive(Clone, Debug)]
+pub struct Node<'i> {
+    pub kind: Rule,
+    pub span: Range<usize>,
+    /// The line it started on in the input
+    pub line: usize,
+    pub children: Vec<Element<'i>>,
+    /// The text of a leaf, ignored if there are children
+    pub leaf: &'i str,
+}
+
+/// A top-level item in the file (a directive, option or unparseable line),
+/// along with the trivia before it and on the rest of its last line.
+/// Indented comment lines straight after the entry are part of it too,
+/// like a comment after the last posting of a transaction.
+#[derive(Clone, Debug)]
+pub struct Entry<'i> {
+    pub leading: Vec<Token<'i>>,
+    pub node: Node<'i>,
+    pub trailing: Vec<Token<'i>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cst<'i> {
+    pub text: &'i str,
+    pub entries: Vec<Entry<'i>>,
+    /// Trivia at the end of the file that isn't attached to any entry
+    pub trailing: Vec<Token<'i>>,
+}
+
+/// Split text that isn't covered by any Node into Tokens
+/// `offset` is the position of `text` in the whole input
+fn tokenize(text: &str, offset: usize, top_level: bool) -> Vec<Token<'_>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let at_line_start = i == 0 && top_level || i > 0 && bytes[i - 1] == b'\n';
+        let until_eol = |i: usize| text[i..].find(['\r', '\n']).map_or(text.len(), |n| i + n);
+        let (kind, end) = match bytes[i] {
+            b' ' | b'\t' => {
+                let n = text[i..].find(|c| c != ' ' && c != '\t');
+                (TokenKind::Whitespace, n.map_or(text.len(), |n| i + n))
+            }
+            b'\n' => (TokenKind::Newline, i + 1),
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => (TokenKind::Newline, i + 2),
+            b';' => (TokenKind::Comment, until_eol(i)),
+            b'*' if top_level && at_line_start => (TokenKind::Heading, until_eol(i)),
+            _ => {
+                let n = text[i..].find([' ', '\t', '\r', '\n', ';']);
+                (TokenKind::Text, n.map_or(text.len(), |n| i + n))
+            }
+        };
+        let end = end.max(i + 1);
+        tokens.push(Token {
+            kind,
+            span: offset + i..offset + end,
+            text: &text[i..end],
+        });
+        i = end;
+    }
+    tokens
+}
+
+impl<'i> Node<'i> {
+    pub fn from_pair(pair: Pair<'i, Rule>, input: &'i str) -> Self {
+        let span = pair.as_span().start()..pair.as_span().end();
+        let mut children: Vec<Element> = Vec::new();
+        let mut pos = span.start;
+        for inner in pair.clone().into_inner() {
+            let start = inner.as_span().start();
+            if start > pos {
+                let gap = tokenize(&input[pos..start], pos, false);
+                children.extend(gap.into_iter().map(Element::Token));
+            }
+            pos = inner.as_span().end();
+            children.push(Element::Node(Node::from_pair(inner, input)));
+        }
+        if !children.is_empty() && pos < span.end {
+            let gap = tokenize(&input[pos..span.end], pos, false);
+            children.extend(gap.into_iter().map(Element::Token));
+        }
+        let (line, _) = pair.line_col();
+        Self {
+            kind: pair.as_rule(),
+            span,
+            line,
+            leaf: match children.is_empty() {
+                true => pair.as_str(),
+                false => "",
+            },
+            children,
+        }
+    }
+
+    /// The text of a leaf, or of everything below it
+    pub fn text(&self) -> Cow<'i, str> {
+        match self.children.is_empty() {
+            true => Cow::Borrowed(self.leaf),
+            false => Cow::Owned(self.to_string()),
+        }
+    }
+
+    /// The child Nodes, skipping Tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &Node<'i>> {
+        self.children.iter().filter_map(|e| match e {
+            Element::Node(n) => Some(n),
+            Element::Token(_) => None,
+        })
+    }
+
+    /// Every Token and leaf Node below this one, in order
+    pub fn leaves(&self) -> Vec<Element<'i>> {
+        if self.children.is_empty() {
+            return vec![Element::Node(self.clone())];
+        }
+        let mut leaves: Vec<Element> = Vec::new();
+        for child in &self.children {
+            match child {
+                Element::Node(n) => leaves.extend(n.leaves()),
+                Element::Token(t) => leaves.push(Element::Token(t.clone())),
+            }
+        }
+        leaves
+    }
+
+    /// The first Node below this one (depth first) of the `kind`
+    pub fn find(&self, kind: Rule) -> Option<&Node<'i>> {
+        self.nodes().find_map(|n| match n.kind == kind {
+            true => Some(n),
+            false => n.find(kind),
+        })
+    }
+
+    /// The innermost Node that contains the byte `offset`
+    pub fn node_at(&self, offset: usize) -> Option<&Node<'i>> {
+        if !self.span.contains(&offset) {
+            return None;
+        }
+        let inner = self.nodes().find_map(|n| n.node_at(offset));
+        Some(inner.unwrap_or(self))
+    }
+
+    /// The innermost Node that contains the byte `offset`, to change in place
+    pub fn node_at_mut(&mut self, offset: usize) -> Option<&mut Node<'i>> {
+        if !self.span.contains(&offset) {
+            return None;
+        }
+        let i = self.children.iter().position(|e| match e {
+            Element::Node(n) => n.span.contains(&offset),
+            Element::Token(_) => false,
+        });
+        match i {
+            Some(i) => match &mut self.children[i] {
+                Element::Node(n) => n.node_at_mut(offset),
+                Element::Token(_) => None,
+            },
+            None => Some(self),
+        }
+    }
+}
+
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.children.is_empty() {
+            return write!(f, "{}", self.leaf);
+        }
+        for child in &self.children {
+            match child {
+                Element::Node(n) => write!(f, "{n}")?,
+                Element::Token(t) => write!(f, "{}", t.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Entry<'_> {
+    /// The span of the entry including its trivia
+    pub fn full_span(&self) -> Range<usize> {
+        let start = self
+            .leading
+            .first()
+            .map_or(self.node.span.start, |t| t.span.start);
+        let end = self
+            .trailing
+            .last()
+            .map_or(self.node.span.end, |t| t.span.end);
+        start..end
+    }
+
+    /// Comments immediately before the entry, with no blank line in between
+    pub fn leading_comments(&self) -> Vec<&Token<'_>> {
+        let mut comments: Vec<&Token> = Vec::new();
+        let mut newlines = 0;
+        for token in self.leading.iter().rev() {
+            match token.kind {
+                TokenKind::Comment => {
+                    comments.push(token);
+                    newlines = 0;
+                }
+                TokenKind::Newline => {
+                    newlines += 1;
+                    if newlines > 1 {
+                        break;
+                    }
+                }
+                TokenKind::Whitespace => (),
+                TokenKind::Heading | TokenKind::Text => break,
+            }
+        }
+        comments.reverse();
+        comments
+    }
+
+    /// A comment on the same line, after the end of the entry
+    pub fn trailing_comment(&self) -> Option<&Token<'_>> {
+        self.trailing
+            .iter()
+            .take_while(|t| t.kind != TokenKind::Newline)
+            .find(|t| t.kind == TokenKind::Comment)
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for t in &self.leading {
+            write!(f, "{}", t.text)?;
+        }
+        write!(f, "{}", self.node)?;
+        for t in &self.trailing {
+            write!(f, "{}", t.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Move the rest of the previous entry's last line, and any indented
+/// comment lines after it, from the start of `gap` to the entry
+/// Returns what is left of the gap
+fn attach_trailing<'i>(prev: Option<&mut Entry<'i>>, gap: Vec<Token<'i>>) -> Vec<Token<'i>> {
+    let Some(prev) = prev else {
+        return gap;
+    };
+    let kinds: Vec<TokenKind> = gap.iter().map(|t| t.kind).collect();
+    let mut end = match kinds.iter().position(|k| *k == TokenKind::Newline) {
+        Some(i) => i + 1,
+        None => kinds.len(),
+    };
+    loop {
+        let rest = &kinds[end..];
+        let indented_comment =
+            rest.len() > 1 && rest[0] == TokenKind::Whitespace && rest[1] == TokenKind::Comment;
+        if !indented_comment {
+            break;
+        }
+        end += match rest.get(2) {
+            Some(TokenKind::Newline) => 3,
+            _ => 2,
+        };
+    }
+    let mut gap = gap;
+    let rest = gap.split_off(end);
+    prev.trailing.extend(gap);
+    rest
+}
+
+/// Parse the text into a lossless Cst
+pub fn parse(text: &str) -> Cst<'_> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut pos = 0;
+    // Trivia waiting to be attached to the next entry
+    let mut pending: Vec<Token> = Vec::new();
+    for pair in loader::load(text) {
+        if pair.as_rule() == Rule::EOI {
+            continue;
+        }
+        let start = pair.as_span().start();
+        let gap = tokenize(&text[pos..start], pos, true);
+        pending.extend(attach_trailing(entries.last_mut(), gap));
+        pos = pair.as_span().end();
+        entries.push(Entry {
+            leading: std::mem::take(&mut pending),
+            node: Node::from_pair(pair, text),
+            trailing: Vec::new(),
+        });
+    }
+    let gap = tokenize(&text[pos..], pos, true);
+    let trailing = attach_trailing(entries.last_mut(), gap);
+    Cst {
+        text,
+        entries,
+        trailing,
+    }
+}
+
+impl Cst<'_> {
+    /// The entry whose full span (including trivia) contains the byte `offset`
+    pub fn entry_at(&self, offset: usize) -> Option<&Entry<'_>> {
+        self.entries
+            .iter()
+            .find(|e| e.full_span().contains(&offset))
+    }
+
+    /// Replace the text in `span` to give a new version of the input,
+    /// leaving everything else exactly as it was
+    pub fn edit(&self, span: Range<usize>, replacement: &str) -> String {
+        let mut out = String::with_capacity(self.text.len() + replacement.len());
+        out.push_str(&self.text[..span.start]);
+        out.push_str(replacement);
+        out.push_str(&self.text[span.end..]);
+        out
+    }
+}
+
+impl fmt::Display for Cst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in &self.entries {
+            write!(f, "{e}")?;
+        }
+        for t in &self.trailing {
+            write!(f, "{}", t.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "* Heading
+
+; about the account
+; more about it
+2023-01-01 open Assets:Bank GBP ; trailing
+  note: \"x\"
+  ; after the meta
+
+2023-01-02 * \"Shop\"
+  Assets:Bank  -1,000.00 GBP ; on the posting
+  ; between postings
+  Expenses:Food
+2023-01-03 foo
+; the end
+";
+
+    #[test]
+    fn test_lossless() {
+        let cst = parse(TEXT);
+        assert_eq!(cst.to_string(), TEXT);
+        assert!(cst.entries.len() == 3);
+        assert!(cst.entries[2].node.kind == Rule::badline);
+        assert!(cst.trailing.iter().any(|t| t.text == "; the end"));
+    }
+
+    #[test]
+    fn test_trivia() {
+        let cst = parse(TEXT);
+        let open = &cst.entries[0];
+        assert!(open.node.kind == Rule::open);
+        let heading = open.leading.iter().find_map(|t| t.heading());
+        assert!(heading == Some((1, "Heading")));
+        let comments: Vec<&str> = open.leading_comments().iter().map(|t| t.text).collect();
+        assert!(comments == vec!["; about the account", "; more about it"]);
+
+        let tx = &cst.entries[1].node;
+        assert!(tx.kind == Rule::transaction);
+        let comments: Vec<&str> = tx
+            .leaves()
+            .iter()
+            .filter_map(|e| match e {
+                Element::Token(t) if t.kind == TokenKind::Comment => Some(t.text),
+                _ => None,
+            })
+            .collect();
+        assert!(comments == vec!["; on the posting", "; between postings"]);
+        let after = &open.trailing;
+        assert!(after.iter().any(|t| t.text == "; after the meta"));
+        assert!(open.trailing_comment().is_none());
+        assert!(!cst.entries[1]
+            .leading
+            .iter()
+            .any(|t| t.kind == TokenKind::Comment));
+    }
+
+    #[test]
+    fn test_surgical_edit() {
+        let cst = parse(TEXT);
+        let offset = TEXT.find("1,000.00").unwrap();
+        let tx = &cst.entry_at(offset).unwrap().node;
+        let number = tx.node_at(offset).unwrap();
+        assert!(number.kind == Rule::number);
+        let edited = cst.edit(number.span.clone(), "2,000.00");
+        assert_eq!(edited, TEXT.replace("1,000.00", "2,000.00"));
+    }
+}
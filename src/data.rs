@@ -1,32 +1,102 @@
 /// Only those types in the enum Directives are direct members of the Ledger.
 /// The rest are children of other elements.
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::iter::Peekable;
 
 use chrono::NaiveDate;
-use pest::iterators::{Pair, Pairs};
 use pyo3::pyclass;
 use rust_decimal::Decimal;
 
+use crate::cst::Node;
+use crate::error::{BeanError, ErrorType};
 use crate::grammar::Rule;
+use crate::number;
+use crate::utils;
 
-const BASE_DATE: &str = "0001-01-01";
 pub const DATE_FMT: &str = "%Y-%m-%d";
 
 type Ccy = String;
 pub type Account = String;
 
-pub type CcyBal = HashMap<Ccy, Decimal>;
-pub type AccBal = HashMap<Account, CcyBal>;
-pub type AccStatuses = HashMap<Account, (bool, Vec<Ccy>)>;
+pub type CcyBal = BTreeMap<Ccy, Decimal>;
+pub type AccBal = BTreeMap<Account, CcyBal>;
+pub type AccStatuses = BTreeMap<Account, (bool, Vec<Ccy>)>;
+
+/// The five kinds of account, as determined by the root of the account name
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AccountType {
+    Assets,
+    Liabilities,
+    Equity,
+    Income,
+    Expenses,
+}
+
+impl AccountType {
+    pub const ALL: [AccountType; 5] = [
+        AccountType::Assets,
+        AccountType::Liabilities,
+        AccountType::Equity,
+        AccountType::Income,
+        AccountType::Expenses,
+    ];
+
+    /// Assets, Liabilities and Equity make up the balance sheet
+    pub fn is_balance_sheet(&self) -> bool {
+        matches!(
+            self,
+            AccountType::Assets | AccountType::Liabilities | AccountType::Equity
+        )
+    }
+
+    /// Income and Expenses make up the income statement
+    pub fn is_income_statement(&self) -> bool {
+        !self.is_balance_sheet()
+    }
+
+    /// Multiply balances by this to show them the way people expect:
+    /// Liabilities, Equity and Income are credits so are shown negated
+    pub fn sign(&self) -> Decimal {
+        match self {
+            AccountType::Assets | AccountType::Expenses => Decimal::ONE,
+            _ => Decimal::NEGATIVE_ONE,
+        }
+    }
+}
 
 #[pyclass]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Options {
     #[pyo3(get)]
     pub title: String,
     #[pyo3(get)]
     pub operating_currency: String,
+    #[pyo3(get)]
+    pub name_assets: String,
+    #[pyo3(get)]
+    pub name_liabilities: String,
+    #[pyo3(get)]
+    pub name_equity: String,
+    #[pyo3(get)]
+    pub name_income: String,
+    #[pyo3(get)]
+    pub name_expenses: String,
+    /// The Equity subaccount that balances from before a period are summarized against
+    #[pyo3(get)]
+    pub account_previous_balances: String,
+    /// The Equity subaccount that Income and Expenses from before a period are moved to
+    #[pyo3(get)]
+    pub account_previous_earnings: String,
+    /// The Equity subaccount that Income and Expenses are moved to when closing the books
+    #[pyo3(get)]
+    pub account_current_earnings: String,
+    /// The Equity subaccount that balances left by conversions between currencies are moved to
+    #[pyo3(get)]
+    pub account_current_conversions: String,
+    /// The currency that conversions are priced in, at zero
+    #[pyo3(get)]
+    pub conversion_currency: String,
 }
 
 impl Default for Options {
@@ -34,21 +104,179 @@ impl Default for Options {
         Self {
             title: "".to_string(),
             operating_currency: "".to_string(),
+            name_assets: "Assets".to_string(),
+            name_liabilities: "Liabilities".to_string(),
+            name_equity: "Equity".to_string(),
+            name_income: "Income".to_string(),
+            name_expenses: "Expenses".to_string(),
+            account_previous_balances: "Opening-Balances".to_string(),
+            account_previous_earnings: "Earnings:Previous".to_string(),
+            account_current_earnings: "Earnings:Current".to_string(),
+            account_current_conversions: "Conversions:Current".to_string(),
+            conversion_currency: "NOTHING".to_string(),
         }
     }
 }
 
+/// A valid account root is a capitalised word, eg `Assets` or `Aktiva`
+/// As in beancount, any non-ASCII character is also allowed
+fn is_valid_root(name: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || !c.is_ascii();
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_uppercase() || !c.is_ascii() => chars.all(valid),
+        _ => false,
+    }
+}
+
 impl Options {
-    pub fn update_from_entry(&mut self, entry: Pair<Rule>) {
-        let mut pairs = entry.clone().into_inner();
-        let key = pairs.next().unwrap().as_str();
-        let val = pairs.next().unwrap().as_str().to_string();
-        match key {
+    pub fn update_from_node(&mut self, node: &Node) -> Result<(), BeanError> {
+        let mut nodes = node.nodes();
+        let key = get_string(nodes.next().unwrap());
+        let val = get_string(nodes.next().unwrap());
+        let debug = DebugLine::new(node.line);
+        match key.as_str() {
             "title" => self.title = val,
             "operating_currency" => self.operating_currency = val,
-            _ => panic!("Other options not handled yet"),
+            "conversion_currency" => self.conversion_currency = val,
+            "name_assets" | "name_liabilities" | "name_equity" | "name_income"
+            | "name_expenses" => {
+                if !is_valid_root(&val) {
+                    let msg = format!("Invalid account root for option {key}: {val}");
+                    return Err(BeanError::new(ErrorType::InvalidOption, &debug, &msg, None));
+                }
+                match key.as_str() {
+                    "name_assets" => self.name_assets = val,
+                    "name_liabilities" => self.name_liabilities = val,
+                    "name_equity" => self.name_equity = val,
+                    "name_income" => self.name_income = val,
+                    _ => self.name_expenses = val,
+                }
+            }
+            "account_previous_balances"
+            | "account_previous_earnings"
+            | "account_current_earnings"
+            | "account_current_conversions" => {
+                if !val.split(':').all(is_valid_root) {
+                    let msg = format!("Invalid subaccount for option {key}: {val}");
+                    return Err(BeanError::new(ErrorType::InvalidOption, &debug, &msg, None));
+                }
+                match key.as_str() {
+                    "account_previous_balances" => self.account_previous_balances = val,
+                    "account_previous_earnings" => self.account_previous_earnings = val,
+                    "account_current_earnings" => self.account_current_earnings = val,
+                    _ => self.account_current_conversions = val,
+                }
+            }
+            // Valid in beancount, but they don't change anything bean-rs does
+            "filename"
+            | "account_previous_conversions"
+            | "account_unrealized_gains"
+            | "account_rounding"
+            | "inferred_tolerance_default"
+            | "inferred_tolerance_multiplier"
+            | "infer_tolerance_from_cost"
+            | "tolerance"
+            | "default_tolerance"
+            | "use_legacy_fixed_tolerances"
+            | "experiment_explicit_tolerances"
+            | "documents"
+            | "render_commas"
+            | "plugin_processing_mode"
+            | "plugin"
+            | "long_string_maxlines"
+            | "booking_method"
+            | "allow_pipe_separator"
+            | "allow_deprecated_none_for_tags_and_links"
+            | "insert_pythonpath" => (),
+            _ => {
+                let msg = format!("Unknown option: {key}");
+                return Err(BeanError::new(ErrorType::InvalidOption, &debug, &msg, None));
+            }
+        }
+        Ok(())
+    }
+
+    /// The configured root name for an account type
+    pub fn account_root(&self, ty: AccountType) -> &str {
+        match ty {
+            AccountType::Assets => &self.name_assets,
+            AccountType::Liabilities => &self.name_liabilities,
+            AccountType::Equity => &self.name_equity,
+            AccountType::Income => &self.name_income,
+            AccountType::Expenses => &self.name_expenses,
         }
     }
+
+    /// The Equity account that balances from before a period are summarized against
+    pub fn account_previous_balances(&self) -> Account {
+        format!("{}:{}", self.name_equity, self.account_previous_balances)
+    }
+
+    /// The Equity account that Income and Expenses from before a period are moved to
+    pub fn account_previous_earnings(&self) -> Account {
+        format!("{}:{}", self.name_equity, self.account_previous_earnings)
+    }
+
+    /// The Equity account that Income and Expenses within a period are moved to
+    /// when the books are closed
+    pub fn account_current_earnings(&self) -> Account {
+        format!("{}:{}", self.name_equity, self.account_current_earnings)
+    }
+
+    /// The Equity account that balances left by conversions between currencies are moved to
+    pub fn account_current_conversions(&self) -> Account {
+        format!("{}:{}", self.name_equity, self.account_current_conversions)
+    }
+
+    /// Classify an account by its root, using the `name_*` options
+    /// Returns None if the root doesn't match any of them
+    pub fn account_type(&self, account: &str) -> Option<AccountType> {
+        let root = account.split(':').next()?;
+        AccountType::ALL
+            .into_iter()
+            .find(|ty| self.account_root(*ty) == root)
+    }
+
+    /// Sort key for hierarchical account order: roots in the order of
+    /// `AccountType` (unknown roots last), then each child before its siblings' children
+    pub fn account_order<'a>(&self, account: &'a str) -> (usize, Vec<&'a str>) {
+        let rank = self
+            .account_type(account)
+            .map_or(AccountType::ALL.len(), |ty| ty as usize);
+        (rank, account.split(':').collect())
+    }
+
+    /// Sort key for currencies: the operating currency first, then alphabetical
+    pub fn ccy_order<'a>(&self, ccy: &'a str) -> (bool, &'a str) {
+        (ccy != self.operating_currency, ccy)
+    }
+
+    /// The accounts in `bals` in hierarchical order
+    pub fn sorted_accounts<'a>(&self, bals: &'a AccBal) -> Vec<&'a Account> {
+        let mut accounts: Vec<&Account> = bals.keys().collect();
+        accounts.sort_by(|a, b| self.account_order(a).cmp(&self.account_order(b)));
+        accounts
+    }
+
+    /// The amounts in `ccy_bal` with the operating currency first
+    pub fn sorted_ccys<'a>(&self, ccy_bal: &'a CcyBal) -> Vec<(&'a Ccy, &'a Decimal)> {
+        let mut ccys: Vec<(&Ccy, &Decimal)> = ccy_bal.iter().collect();
+        ccys.sort_by(|a, b| self.ccy_order(a.0).cmp(&self.ccy_order(b.0)));
+        ccys
+    }
+}
+
+/// Get the unescaped contents of the string literal in this Node
+/// which may be the `quoted` rule itself or one that wraps it (eg `payee`)
+fn get_string(node: &Node) -> String {
+    let inner = node.find(Rule::inner_quoted).unwrap();
+    utils::unescape(&inner.text())
+}
+
+/// The date in a `date` Node
+fn get_date(node: &Node) -> NaiveDate {
+    NaiveDate::parse_from_str(&node.text(), DATE_FMT).unwrap()
 }
 
 #[derive(Clone, Debug, Default)]
@@ -74,16 +302,19 @@ impl fmt::Display for DebugLine {
     }
 }
 
+#[pyclass]
 #[derive(Clone, Debug)]
 pub struct Amount {
+    #[pyo3(get)]
     pub number: Decimal,
+    #[pyo3(get)]
     pub ccy: Ccy,
 }
 
 impl PartialEq for Amount {
     fn eq(&self, other: &Self) -> bool {
         // TODO get precision from context
-        self.ccy == other.ccy && (self.number - other.number).abs() > Decimal::new(1, 3)
+        self.ccy == other.ccy && (self.number - other.number).abs() <= Decimal::new(1, 3)
     }
 }
 impl Eq for Amount {}
@@ -98,21 +329,11 @@ impl Amount {
     pub fn new(number: Decimal, ccy: Ccy) -> Self {
         Self { number, ccy }
     }
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let mut number: String = pairs.next().unwrap().as_str().to_string();
-        if number.contains(',') {
-            number = number.replace(',', "");
-        }
-        let number: Decimal = match number.parse() {
-            Ok(num) => num,
-            Err(_) => {
-                let (line, _) = entry.line_col();
-                panic!("Un-parseable decimal at line:{line}");
-            }
-        };
-        let ccy = pairs.next().unwrap().as_str().to_string();
-        Self { number, ccy }
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut nodes = node.nodes();
+        let number = number::eval(nodes.next().unwrap())?;
+        let ccy = nodes.next().unwrap().text().to_string();
+        Ok(Self { number, ccy })
     }
 }
 
@@ -120,76 +341,228 @@ impl Amount {
 #[derive(Clone, Debug, PartialEq)]
 pub struct ConfigCustom {
     pub date: NaiveDate,
+    pub name: String,
+    pub values: Vec<MetaValue>,
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl ConfigCustom {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
-        let date = NaiveDate::parse_from_str(BASE_DATE, DATE_FMT).unwrap();
-        Self { date, debug }
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let name = get_string(nodes.next().unwrap());
+        let mut values: Vec<MetaValue> = Vec::new();
+        let mut meta: Vec<Metadata> = Vec::new();
+        for n in nodes {
+            match n.kind {
+                Rule::custom_val => values.push(MetaValue::from_node(n)),
+                Rule::metadata => meta.push(Metadata::from_node(n)),
+                _ => (),
+            }
+        }
+        let debug = DebugLine::new(node.line);
+        Self {
+            date,
+            name,
+            values,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
+            debug,
+        }
     }
 }
 
 impl fmt::Display for ConfigCustom {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "-- ignore custom")
+        let values: Vec<String> = self.values.iter().map(|v| format!(" {v}")).collect();
+        write!(
+            f,
+            "{date} custom {name}{values}{comment}{meta}",
+            date = self.date,
+            name = utils::escape(&self.name),
+            values = values.concat(),
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaValue {
+    /// A quoted string, stored without quotes or escapes
+    Text(String),
+    /// Anything else, stored as written
+    Raw(String),
+}
+
+impl MetaValue {
+    pub fn from_node(node: &Node) -> Self {
+        let inner = node.nodes().next().unwrap();
+        match inner.kind {
+            Rule::quoted => MetaValue::Text(get_string(inner)),
+            _ => MetaValue::Raw(inner.text().to_string()),
+        }
+    }
+    pub fn as_str(&self) -> &str {
+        match self {
+            MetaValue::Text(v) => v,
+            MetaValue::Raw(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetaValue::Text(v) => write!(f, "{}", utils::escape(v)),
+            MetaValue::Raw(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// The `;` comments attached to a Directive or Posting, stored without the `;`
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comments {
+    /// Whole-line comments directly before it
+    #[pyo3(get)]
+    pub leading: Vec<String>,
+    /// A comment at the end of its first line
+    #[pyo3(get)]
+    pub trailing: Option<String>,
+    /// Whole-line comments directly after its first line
+    #[pyo3(get)]
+    pub following: Vec<String>,
+}
+
+impl Comments {
+    /// The text of a comment Token, eg `; about this` gives `about this`
+    pub fn text(comment: &str) -> String {
+        comment
+            .strip_prefix(';')
+            .unwrap_or(comment)
+            .trim()
+            .to_string()
+    }
+    /// Each leading comment on its own line, indented by `indent` spaces
+    pub fn fmt_leading(&self, indent: usize) -> String {
+        let indent = " ".repeat(indent);
+        self.leading
+            .iter()
+            .map(|c| format!("{indent}; {c}\n"))
+            .collect()
+    }
+    /// The trailing comment ready to go at the end of a line,
+    /// then each following comment on its own indented line
+    pub fn fmt_trailing(&self) -> String {
+        let trailing = match &self.trailing {
+            Some(c) => format!(" ; {c}"),
+            None => String::new(),
+        };
+        trailing + &fmt_following(&self.following, 2)
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Metadata {
     pub key: String,
-    pub val: String,
+    pub val: MetaValue,
+    /// A comment at the end of the line
+    pub comment: Option<String>,
+    /// Whole-line comments directly after it
+    pub following: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Metadata {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let key = pairs.next().unwrap().as_str().to_string();
-        let val = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
-        Self { key, val, debug }
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let key = nodes.next().unwrap().text().to_string();
+        let val = MetaValue::from_node(nodes.next().unwrap());
+        let debug = DebugLine::new(node.line);
+        Self {
+            key,
+            val,
+            comment: None,
+            following: Vec::new(),
+            debug,
+        }
     }
 }
 
 impl fmt::Display for Metadata {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "  {key}:{val}", key = self.key, val = self.val,)
+        match self.val.as_str() {
+            "" => write!(f, "{key}:", key = self.key)?,
+            _ => write!(f, "{key}: {val}", key = self.key, val = self.val)?,
+        }
+        if let Some(comment) = &self.comment {
+            write!(f, " ; {comment}")?;
+        }
+        Ok(())
     }
 }
 
+/// Collect all the `metadata` Nodes that are left in `nodes`
+fn get_meta<'a, 'i: 'a>(nodes: impl Iterator<Item = &'a Node<'i>>) -> Vec<Metadata> {
+    nodes
+        .filter(|n| n.kind == Rule::metadata)
+        .map(Metadata::from_node)
+        .collect()
+}
+
+/// Each Metadata on its own line, indented by `indent` spaces,
+/// followed by the comments after it
+fn fmt_meta(meta: &[Metadata], indent: usize) -> String {
+    meta.iter()
+        .map(|m| {
+            let following = fmt_following(&m.following, indent);
+            format!("\n{pad}{m}{following}", pad = " ".repeat(indent))
+        })
+        .collect()
+}
+
+/// Each comment on a new line, indented by `indent` spaces
+fn fmt_following(comments: &[String], indent: usize) -> String {
+    let indent = " ".repeat(indent);
+    comments
+        .iter()
+        .map(|c| format!("\n{indent}; {c}"))
+        .collect()
+}
+
 #[pyclass]
 #[derive(Clone, Debug, PartialEq)]
 pub struct Commodity {
     pub date: NaiveDate,
     pub ccy: String,
     pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Commodity {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let ccy = pairs.next().unwrap().as_str().to_string();
-        let mut meta: Vec<Metadata> = Vec::new();
-        for pair in pairs {
-            if pair.as_rule() == Rule::metadata {
-                let p = Metadata::from_entry(pair);
-                meta.push(p)
-            }
-        }
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let ccy = nodes.next().unwrap().text().to_string();
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
         Self {
             date,
             ccy,
             meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -197,18 +570,13 @@ impl Commodity {
 
 impl fmt::Display for Commodity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut meta_string: String = String::new();
-        let m_slice = &self.meta[..];
-        for m in m_slice {
-            let line: &str = &format!("\n{m}");
-            meta_string.push_str(line);
-        }
         write!(
             f,
-            "{date} commodity {ccy}{meta}",
+            "{date} commodity {ccy}{comment}{meta}",
             date = self.date,
             ccy = self.ccy,
-            meta = meta_string,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -219,30 +587,50 @@ pub struct Open {
     pub date: NaiveDate,
     pub account: Account,
     pub ccys: Vec<Ccy>,
+    pub booking: Option<String>,
     pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Open {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let account = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    /// An Open that isn't in the file, allowing any currency
+    pub fn new(date: NaiveDate, account: Account) -> Self {
+        Self {
+            date,
+            account,
+            ccys: Vec::new(),
+            booking: None,
+            meta: Vec::new(),
+            comments: Comments::default(),
+            section: Vec::new(),
+            debug: DebugLine::default(),
+        }
+    }
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let account = nodes.next().unwrap().text().to_string();
+        let debug = DebugLine::new(node.line);
 
         let mut ccys: Vec<Ccy> = Vec::new();
+        let mut booking: Option<String> = None;
         let mut meta: Vec<Metadata> = Vec::new();
 
-        for pair in pairs {
-            match pair.as_rule() {
+        for n in nodes {
+            match n.kind {
                 Rule::ccy => {
-                    let c = pair.as_str().to_owned();
+                    let c = n.text().to_string();
                     ccys.push(c);
                 }
+                Rule::quoted => {
+                    booking = Some(get_string(n));
+                }
                 Rule::metadata => {
-                    let m = Metadata::from_entry(pair);
+                    let m = Metadata::from_node(n);
                     meta.push(m);
                 }
                 _ => (),
@@ -253,7 +641,10 @@ impl Open {
             date,
             account,
             ccys,
+            booking,
             meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -261,11 +652,21 @@ impl Open {
 
 impl fmt::Display for Open {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ccys = match self.ccys.is_empty() {
+            true => String::new(),
+            false => format!(" {}", self.ccys.join(", ")),
+        };
+        let booking = match &self.booking {
+            Some(booking) => format!(" {}", utils::escape(booking)),
+            None => String::new(),
+        };
         write!(
             f,
-            "{date} {account}",
+            "{date} open {account}{ccys}{booking}{comment}{meta}",
             date = self.date,
             account = self.account,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -275,21 +676,27 @@ impl fmt::Display for Open {
 pub struct Close {
     pub date: NaiveDate,
     pub account: Account,
-    // TODO can also have Meta
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Close {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let account = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let account = nodes.next().unwrap().text().to_string();
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
         Self {
             date,
             account,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -299,9 +706,11 @@ impl fmt::Display for Close {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} {account}",
+            "{date} close {account}{comment}{meta}",
             date = self.date,
             account = self.account,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -312,26 +721,31 @@ pub struct Balance {
     pub date: NaiveDate,
     pub account: Account,
     pub amount: Amount,
-    // TODO can also have Meta
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Balance {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let account = pairs.next().unwrap().as_str().to_string();
-        let amount_entry = pairs.next().unwrap();
-        let amount = Amount::from_entry(amount_entry);
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
-        Self {
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let account = nodes.next().unwrap().text().to_string();
+        let amount = Amount::from_node(nodes.next().unwrap())?;
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
+        Ok(Self {
             date,
             account,
             amount,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
-        }
+        })
     }
 }
 
@@ -339,10 +753,12 @@ impl fmt::Display for Balance {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} {account} {amount}",
+            "{date} balance {account} {amount}{comment}{meta}",
             date = self.date,
             account = self.account,
             amount = self.amount,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -353,23 +769,29 @@ pub struct Pad {
     pub date: NaiveDate,
     pub account_to: Account,
     pub account_from: Account,
-    // TODO can also have Meta
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Pad {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let account_to = pairs.next().unwrap().as_str().to_string();
-        let account_from = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let account_to = nodes.next().unwrap().text().to_string();
+        let account_from = nodes.next().unwrap().text().to_string();
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
         Self {
             date,
             account_to,
             account_from,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -379,10 +801,12 @@ impl fmt::Display for Pad {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} {account_to} {account_from}",
+            "{date} pad {account_to} {account_from}{comment}{meta}",
             date = self.date,
             account_to = self.account_to,
             account_from = self.account_from,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -393,26 +817,31 @@ pub struct Price {
     pub date: NaiveDate,
     pub commodity: String,
     pub amount: Amount,
-    // TODO can also have Meta
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Price {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let commodity = pairs.next().unwrap().as_str().to_string();
-        let amount_entry = pairs.next().unwrap();
-        let amount = Amount::from_entry(amount_entry);
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
-        Self {
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let commodity = nodes.next().unwrap().text().to_string();
+        let amount = Amount::from_node(nodes.next().unwrap())?;
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
+        Ok(Self {
             date,
             commodity,
             amount,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
-        }
+        })
     }
 }
 
@@ -420,10 +849,12 @@ impl fmt::Display for Price {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} {commodity} {amount}",
+            "{date} price {commodity} {amount}{comment}{meta}",
             date = self.date,
             commodity = self.commodity,
             amount = self.amount,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -434,23 +865,29 @@ pub struct Document {
     pub date: NaiveDate,
     pub account: Account,
     pub path: String,
-    // TODO can also have Meta
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Document {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let account = pairs.next().unwrap().as_str().to_string();
-        let path = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let account = nodes.next().unwrap().text().to_string();
+        let path = get_string(nodes.next().unwrap());
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
         Self {
             date,
             account,
             path,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -460,10 +897,12 @@ impl fmt::Display for Document {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} document {account} {path}",
+            "{date} document {account} {path}{comment}{meta}",
             date = self.date,
             account = self.account,
-            path = self.path,
+            path = utils::escape(&self.path),
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -474,23 +913,29 @@ pub struct Note {
     pub date: NaiveDate,
     pub account: Account,
     pub note: String,
-    // TODO can also have Meta
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Note {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let account = pairs.next().unwrap().as_str().to_string();
-        let note = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let account = nodes.next().unwrap().text().to_string();
+        let note = get_string(nodes.next().unwrap());
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
         Self {
             date,
             account,
             note,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -500,10 +945,12 @@ impl fmt::Display for Note {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} note {account} {note}",
+            "{date} note {account} {note}{comment}{meta}",
             date = self.date,
             account = self.account,
-            note = self.note,
+            note = utils::escape(&self.note),
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
@@ -514,22 +961,29 @@ pub struct Query {
     pub date: NaiveDate,
     pub name: String,
     pub query: String,
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
 }
 
 impl Query {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let name = pairs.next().unwrap().as_str().to_string();
-        let query = pairs.next().unwrap().as_str().to_string();
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
+    pub fn from_node(node: &Node) -> Self {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let name = get_string(nodes.next().unwrap());
+        let query = get_string(nodes.next().unwrap());
+        let meta = get_meta(nodes);
+        let debug = DebugLine::new(node.line);
         Self {
             date,
             name,
             query,
+            meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
         }
     }
@@ -539,18 +993,89 @@ impl fmt::Display for Query {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{date} query {name} {query}",
+            "{date} query {name} {query}{comment}{meta}",
             date = self.date,
-            name = self.name,
-            query = self.query,
+            name = utils::escape(&self.name),
+            query = utils::escape(&self.query),
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
         )
     }
 }
 
+/// A cost specification such as `{10 GBP}`
+/// or an empty `{}` where the cost is left to be inferred
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cost {
+    pub amount: Option<Amount>,
+}
+
+impl Cost {
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut nodes = node.nodes();
+        let amount = match nodes.next() {
+            Some(number) => {
+                let number = number::eval(number)?;
+                let ccy = nodes.next().unwrap().text().to_string();
+                Some(Amount::new(number, ccy))
+            }
+            None => None,
+        };
+        Ok(Self { amount })
+    }
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.amount {
+            Some(amount) => write!(f, "{{{amount}}}"),
+            None => write!(f, "{{}}"),
+        }
+    }
+}
+
+/// A per-unit price such as `@ 1.2 USD`, where the number can be left out
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitPrice {
+    pub number: Option<Decimal>,
+    pub ccy: Ccy,
+}
+
+impl UnitPrice {
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut number: Option<Decimal> = None;
+        let mut ccy = String::new();
+        for n in node.nodes() {
+            match n.kind {
+                Rule::num_expr => number = Some(number::eval(n)?),
+                _ => ccy = n.text().to_string(),
+            }
+        }
+        Ok(Self { number, ccy })
+    }
+}
+
+impl fmt::Display for UnitPrice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.number {
+            Some(number) => write!(f, "@ {number} {ccy}", ccy = self.ccy),
+            None => write!(f, "@ {ccy}", ccy = self.ccy),
+        }
+    }
+}
+
+#[pyclass]
 #[derive(Clone, Debug, PartialEq)]
 pub struct Posting {
+    #[pyo3(get)]
     pub account: Account,
+    #[pyo3(get)]
     pub amount: Option<Amount>,
+    pub cost: Option<Cost>,
+    pub price: Option<UnitPrice>,
+    pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
     pub debug: Option<DebugLine>,
 }
 
@@ -561,39 +1086,59 @@ impl Posting {
         Self {
             account,
             amount,
+            cost: None,
+            price: None,
+            meta: Vec::new(),
+            comments: Comments::default(),
             debug,
         }
     }
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let account = pairs.next().unwrap().as_str().to_string();
-        let amount = if pairs.peek().is_some() {
-            Some(Amount::from_entry(pairs.next().unwrap()))
-        } else {
-            None
-        };
-        let (line, _) = entry.line_col();
-        let debug = Some(DebugLine { line });
-        Self {
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut nodes = node.nodes();
+        let account = nodes.next().unwrap().text().to_string();
+        let mut amount: Option<Amount> = None;
+        let mut cost: Option<Cost> = None;
+        let mut price: Option<UnitPrice> = None;
+        let mut meta: Vec<Metadata> = Vec::new();
+        for n in nodes {
+            match n.kind {
+                Rule::amount => amount = Some(Amount::from_node(n)?),
+                Rule::at_cost => cost = Some(Cost::from_node(n)?),
+                Rule::at_price => price = Some(UnitPrice::from_node(n)?),
+                Rule::metadata => meta.push(Metadata::from_node(n)),
+                _ => (),
+            }
+        }
+        let debug = Some(DebugLine::new(node.line));
+        Ok(Self {
             account,
             amount,
+            cost,
+            price,
+            meta,
+            comments: Comments::default(),
             debug,
-        }
+        })
     }
 }
 
 impl fmt::Display for Posting {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let amount_str = match &self.amount {
-            Some(amount) => amount.to_string(),
-            None => String::new(),
-        };
-
+        let mut line = format!("  {account}", account = self.account);
+        if let Some(amount) = &self.amount {
+            line.push_str(&format!("  {amount}"));
+        }
+        if let Some(cost) = &self.cost {
+            line.push_str(&format!(" {cost}"));
+        }
+        if let Some(price) = &self.price {
+            line.push_str(&format!(" {price}"));
+        }
         write!(
             f,
-            "  {account} {amount}",
-            account = self.account,
-            amount = amount_str,
+            "{line}{comment}{meta}",
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 4)
         )
     }
 }
@@ -607,19 +1152,27 @@ pub struct Transaction {
     pub payee: Option<String>,
     #[pyo3(get)]
     pub narration: String,
-    pub tag: Option<String>,  // TODO can have multiple
-    pub link: Option<String>, // TODO can have multiple
+    #[pyo3(get)]
+    pub tags: Vec<String>,
+    #[pyo3(get)]
+    pub links: Vec<String>,
+    #[pyo3(get)]
     pub postings: Vec<Posting>,
     pub meta: Vec<Metadata>,
+    #[pyo3(get)]
+    pub comments: Comments,
+    #[pyo3(get)]
+    pub section: Vec<String>,
     pub debug: DebugLine,
-    // TODO add at_cost, at_price
 }
 
-fn get_payee_narration(pairs: &mut Pairs<Rule>) -> (Option<String>, String) {
-    let first_val = pairs.next().unwrap().as_str().to_string();
-    if let Some(pair) = pairs.peek() {
-        if pair.as_rule() == Rule::narration {
-            let narration = pairs.next().unwrap().as_str().to_string();
+fn get_payee_narration<'a, 'i: 'a>(
+    nodes: &mut Peekable<impl Iterator<Item = &'a Node<'i>>>,
+) -> (Option<String>, String) {
+    let first_val = get_string(nodes.next().unwrap());
+    if let Some(n) = nodes.peek() {
+        if n.kind == Rule::narration {
+            let narration = get_string(nodes.next().unwrap());
             return (Some(first_val), narration);
         }
     }
@@ -627,74 +1180,86 @@ fn get_payee_narration(pairs: &mut Pairs<Rule>) -> (Option<String>, String) {
 }
 
 impl Transaction {
-    pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut pairs = entry.clone().into_inner();
-        let date = pairs.next().unwrap().as_str();
-        let date = NaiveDate::parse_from_str(date, DATE_FMT).unwrap();
-        let ty = pairs.next().unwrap().as_str().to_string();
-        let (payee, narration) = get_payee_narration(&mut pairs);
+    pub fn from_node(node: &Node) -> Result<Self, BeanError> {
+        let mut nodes = node.nodes();
+        let date = get_date(nodes.next().unwrap());
+        let ty = nodes.next().unwrap().text().to_string();
+        let mut nodes = nodes.peekable();
+        let (payee, narration) = get_payee_narration(&mut nodes);
         let mut postings: Vec<Posting> = Vec::new();
         let mut meta: Vec<Metadata> = Vec::new();
-        let mut link: Option<String> = None;
-        let mut tag: Option<String> = None;
-        for pair in pairs {
-            match pair.as_rule() {
+        let mut links: Vec<String> = Vec::new();
+        let mut tags: Vec<String> = Vec::new();
+        for n in nodes {
+            match n.kind {
                 Rule::posting => {
-                    postings.push(Posting::from_entry(pair));
+                    postings.push(Posting::from_node(n)?);
                 }
                 Rule::metadata => {
-                    meta.push(Metadata::from_entry(pair));
+                    meta.push(Metadata::from_node(n));
                 }
                 Rule::link => {
-                    link = Some(entry.as_str().to_owned());
+                    // Skip the leading ^
+                    links.push(n.text()[1..].to_owned());
                 }
                 Rule::tag => {
-                    tag = Some(entry.as_str().to_owned());
+                    // Skip the leading #
+                    tags.push(n.text()[1..].to_owned());
                 }
                 _ => {
-                    let (line, _) = entry.line_col();
-                    let debug = DebugLine::new(line);
+                    let debug = DebugLine::new(node.line);
                     unreachable!("Unexpected entry in Transaction, abort.\n{debug}");
                 }
             }
         }
-        let (line, _) = entry.line_col();
-        let debug = DebugLine { line };
-        Self {
+        let debug = DebugLine::new(node.line);
+        Ok(Self {
             date,
             ty,
             payee,
             narration,
-            tag,
-            link,
+            tags,
+            links,
             postings,
             meta,
+            comments: Comments::default(),
+            section: Vec::new(),
             debug,
+        })
+    }
+    /// A Transaction that isn't in the file, eg one that summarizes a period
+    pub fn new(date: NaiveDate, ty: &str, narration: String, postings: Vec<Posting>) -> Self {
+        Self {
+            date,
+            ty: ty.to_string(),
+            payee: None,
+            narration,
+            tags: Vec::new(),
+            links: Vec::new(),
+            postings,
+            meta: Vec::new(),
+            comments: Comments::default(),
+            section: Vec::new(),
+            debug: DebugLine::default(),
         }
     }
     pub fn from_pad(pad: Pad, amount: Amount) -> Self {
         let date = pad.date;
-        let ty = String::from("pad");
+        let ty = String::from("P");
         let payee = None;
-        let narration = String::new();
+        let narration = format!(
+            "(Padding inserted for Balance of {amount} for {account})",
+            account = pad.account_to
+        );
         let debug: DebugLine = DebugLine::default();
-        let link = None;
-        let tag = None;
-        let amount2 = Some(Amount {
+        let amount2 = Amount {
             number: -amount.clone().number,
             ccy: amount.clone().ccy,
-        });
-        let amount = Some(amount);
-        let p1 = Posting {
-            account: pad.account_to,
-            amount: amount.clone(),
-            debug: Some(debug.clone()),
-        };
-        let p2 = Posting {
-            account: pad.account_from,
-            amount: amount2,
-            debug: Some(debug.clone()),
         };
+        let mut p1 = Posting::new(pad.account_to, amount.number, amount.ccy);
+        p1.debug = Some(debug.clone());
+        let mut p2 = Posting::new(pad.account_from, amount2.number, amount2.ccy);
+        p2.debug = Some(debug.clone());
         let postings = vec![p1, p2];
         let meta: Vec<Metadata> = Vec::new();
         Self {
@@ -702,10 +1267,12 @@ impl Transaction {
             ty,
             payee,
             narration,
-            link,
-            tag,
+            tags: Vec::new(),
+            links: Vec::new(),
             postings,
             meta,
+            comments: Comments::default(),
+            section: pad.section,
             debug: debug.clone(),
         }
     }
@@ -714,32 +1281,34 @@ impl Transaction {
 impl fmt::Display for Transaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let payee_str = match &self.payee {
-            Some(payee) => payee.as_str(),
-            None => "",
+            Some(payee) => format!("{} ", utils::escape(payee)),
+            None => String::new(),
         };
 
-        let mut posting_string = String::new();
-        let slice = &self.postings[..];
-        for p in slice {
-            let line: &str = &format!("\n{p}");
-            posting_string.push_str(line);
+        let mut tags_string = String::new();
+        for tag in &self.tags {
+            tags_string.push_str(&format!(" #{tag}"));
+        }
+        for link in &self.links {
+            tags_string.push_str(&format!(" ^{link}"));
         }
 
-        let mut meta_string = String::new();
-        let m_slice = &self.meta[..];
-        for m in m_slice {
-            let line: &str = &format!("\n{m}");
-            meta_string.push_str(line);
+        let mut posting_string = String::new();
+        for p in &self.postings {
+            let leading = p.comments.fmt_leading(2);
+            posting_string.push_str(&format!("\n{leading}{p}"));
         }
 
         write!(
             f,
-            "{date} {ty} {payee} {narration}{meta}{postings}",
+            "{date} {ty} {payee}{narration}{tags}{comment}{meta}{postings}",
             date = self.date,
             ty = self.ty,
             payee = payee_str,
-            narration = self.narration,
-            meta = meta_string,
+            narration = utils::escape(&self.narration),
+            tags = tags_string,
+            comment = self.comments.fmt_trailing(),
+            meta = fmt_meta(&self.meta, 2),
             postings = posting_string,
         )
     }
@@ -748,7 +1317,7 @@ impl fmt::Display for Transaction {
 /// The "ledger" is made up of Directives
 /// Most operations will be done by looping through a Vec of these
 #[pyclass]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Directive {
     ConfigCustom(ConfigCustom),
     Commodity(Commodity),
@@ -779,6 +1348,118 @@ impl Directive {
             Directive::Transaction(d) => &d.date,
         }
     }
+    /// All the accounts referred to by this Directive
+    pub fn accounts(&self) -> Vec<&Account> {
+        match self {
+            Directive::Open(d) => vec![&d.account],
+            Directive::Close(d) => vec![&d.account],
+            Directive::Balance(d) => vec![&d.account],
+            Directive::Pad(d) => vec![&d.account_to, &d.account_from],
+            Directive::Document(d) => vec![&d.account],
+            Directive::Note(d) => vec![&d.account],
+            Directive::Transaction(d) => d.postings.iter().map(|p| &p.account).collect(),
+            Directive::ConfigCustom(_)
+            | Directive::Commodity(_)
+            | Directive::Price(_)
+            | Directive::Query(_) => Vec::new(),
+        }
+    }
+    pub fn debug(&self) -> &DebugLine {
+        match self {
+            Directive::ConfigCustom(d) => &d.debug,
+            Directive::Commodity(d) => &d.debug,
+            Directive::Open(d) => &d.debug,
+            Directive::Close(d) => &d.debug,
+            Directive::Balance(d) => &d.debug,
+            Directive::Pad(d) => &d.debug,
+            Directive::Price(d) => &d.debug,
+            Directive::Document(d) => &d.debug,
+            Directive::Note(d) => &d.debug,
+            Directive::Query(d) => &d.debug,
+            Directive::Transaction(d) => &d.debug,
+        }
+    }
+    /// The path of org-mode headings that this Directive sits under,
+    /// eg `["Example beancount file", "Transactions"]`
+    pub fn section(&self) -> &[String] {
+        match self {
+            Directive::ConfigCustom(d) => &d.section,
+            Directive::Commodity(d) => &d.section,
+            Directive::Open(d) => &d.section,
+            Directive::Close(d) => &d.section,
+            Directive::Balance(d) => &d.section,
+            Directive::Pad(d) => &d.section,
+            Directive::Price(d) => &d.section,
+            Directive::Document(d) => &d.section,
+            Directive::Note(d) => &d.section,
+            Directive::Query(d) => &d.section,
+            Directive::Transaction(d) => &d.section,
+        }
+    }
+    pub fn set_section(&mut self, section: Vec<String>) {
+        match self {
+            Directive::ConfigCustom(d) => d.section = section,
+            Directive::Commodity(d) => d.section = section,
+            Directive::Open(d) => d.section = section,
+            Directive::Close(d) => d.section = section,
+            Directive::Balance(d) => d.section = section,
+            Directive::Pad(d) => d.section = section,
+            Directive::Price(d) => d.section = section,
+            Directive::Document(d) => d.section = section,
+            Directive::Note(d) => d.section = section,
+            Directive::Query(d) => d.section = section,
+            Directive::Transaction(d) => d.section = section,
+        }
+    }
+    /// Whether the Directive is under a heading called `heading`, at any level
+    pub fn in_section(&self, heading: &str) -> bool {
+        self.section().iter().any(|h| h == heading)
+    }
+    pub fn comments(&self) -> &Comments {
+        match self {
+            Directive::ConfigCustom(d) => &d.comments,
+            Directive::Commodity(d) => &d.comments,
+            Directive::Open(d) => &d.comments,
+            Directive::Close(d) => &d.comments,
+            Directive::Balance(d) => &d.comments,
+            Directive::Pad(d) => &d.comments,
+            Directive::Price(d) => &d.comments,
+            Directive::Document(d) => &d.comments,
+            Directive::Note(d) => &d.comments,
+            Directive::Query(d) => &d.comments,
+            Directive::Transaction(d) => &d.comments,
+        }
+    }
+    pub fn comments_mut(&mut self) -> &mut Comments {
+        match self {
+            Directive::ConfigCustom(d) => &mut d.comments,
+            Directive::Commodity(d) => &mut d.comments,
+            Directive::Open(d) => &mut d.comments,
+            Directive::Close(d) => &mut d.comments,
+            Directive::Balance(d) => &mut d.comments,
+            Directive::Pad(d) => &mut d.comments,
+            Directive::Price(d) => &mut d.comments,
+            Directive::Document(d) => &mut d.comments,
+            Directive::Note(d) => &mut d.comments,
+            Directive::Query(d) => &mut d.comments,
+            Directive::Transaction(d) => &mut d.comments,
+        }
+    }
+    pub fn meta_mut(&mut self) -> &mut Vec<Metadata> {
+        match self {
+            Directive::ConfigCustom(d) => &mut d.meta,
+            Directive::Commodity(d) => &mut d.meta,
+            Directive::Open(d) => &mut d.meta,
+            Directive::Close(d) => &mut d.meta,
+            Directive::Balance(d) => &mut d.meta,
+            Directive::Pad(d) => &mut d.meta,
+            Directive::Price(d) => &mut d.meta,
+            Directive::Document(d) => &mut d.meta,
+            Directive::Note(d) => &mut d.meta,
+            Directive::Query(d) => &mut d.meta,
+            Directive::Transaction(d) => &mut d.meta,
+        }
+    }
     /// This follows beancount's ordering logic, that always evaluates
     /// opens -> balances -> the rest -> documents -> closes
     pub fn order(&self) -> i8 {
@@ -800,18 +1481,19 @@ impl Directive {
 
 impl fmt::Display for Directive {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let leading = self.comments().fmt_leading(0);
         match self {
-            Directive::ConfigCustom(d) => write!(f, "{d}"),
-            Directive::Commodity(d) => write!(f, "{d}"),
-            Directive::Open(d) => write!(f, "{d}"),
-            Directive::Close(d) => write!(f, "{d}"),
-            Directive::Balance(d) => write!(f, "{d}"),
-            Directive::Pad(d) => write!(f, "{d}"),
-            Directive::Price(d) => write!(f, "{d}"),
-            Directive::Document(d) => write!(f, "{d}"),
-            Directive::Note(d) => write!(f, "{d}"),
-            Directive::Query(d) => write!(f, "{d}"),
-            Directive::Transaction(d) => write!(f, "{d}"),
+            Directive::ConfigCustom(d) => write!(f, "{leading}{d}"),
+            Directive::Commodity(d) => write!(f, "{leading}{d}"),
+            Directive::Open(d) => write!(f, "{leading}{d}"),
+            Directive::Close(d) => write!(f, "{leading}{d}"),
+            Directive::Balance(d) => write!(f, "{leading}{d}"),
+            Directive::Pad(d) => write!(f, "{leading}{d}"),
+            Directive::Price(d) => write!(f, "{leading}{d}"),
+            Directive::Document(d) => write!(f, "{leading}{d}"),
+            Directive::Note(d) => write!(f, "{leading}{d}"),
+            Directive::Query(d) => write!(f, "{leading}{d}"),
+            Directive::Transaction(d) => write!(f, "{leading}{d}"),
         }
     }
 }
@@ -819,23 +1501,26 @@ impl fmt::Display for Directive {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{ledger::Ledger, loader};
+    use crate::{cst, ledger::Ledger, loader};
 
     #[test]
     fn test_open() {
         let text = r#"2023-01-01 open Assets:Bank GBP"#;
-        let entries = loader::load(&text);
+        let cst = cst::parse(text);
         let Ledger {
             dirs,
             errs: _,
             opts: _,
-        } = loader::consume(entries);
+        } = loader::consume(&cst);
         let date = NaiveDate::parse_from_str("2023-01-01", DATE_FMT).unwrap();
         let a = &Open {
             date,
             account: String::from("Assets:Bank"),
             ccys: vec!["GBP".to_owned()],
+            booking: None,
             meta: Vec::new(),
+            comments: Comments::default(),
+            section: Vec::new(),
             debug: DebugLine { line: 2 },
         };
         let got = &dirs[0];
@@ -843,18 +1528,109 @@ mod tests {
             Directive::Open(i) => {
                 assert!(i == a);
             }
-            _ => assert!(false, "Found wrong directive type"),
+            _ => panic!("Found wrong directive type"),
         }
     }
 
     #[test]
-    #[should_panic]
+    fn test_sort_order() {
+        let opts = Options {
+            operating_currency: "GBP".to_string(),
+            ..Default::default()
+        };
+        let mut bals: AccBal = BTreeMap::new();
+        for account in [
+            "Expenses:Food",
+            "Assets:Bank:Savings",
+            "Assets:Bank-Old",
+            "Income:Job",
+            "Assets:Bank",
+            "Liabilities:Card",
+            "Other:Thing",
+        ] {
+            bals.insert(account.to_string(), BTreeMap::new());
+        }
+        let accounts = opts.sorted_accounts(&bals);
+        let want = [
+            "Assets:Bank",
+            "Assets:Bank:Savings",
+            "Assets:Bank-Old",
+            "Liabilities:Card",
+            "Income:Job",
+            "Expenses:Food",
+            "Other:Thing",
+        ];
+        assert!(accounts == want);
+
+        let ccy_bal: CcyBal = ["USD", "EUR", "GBP"]
+            .map(|c| (c.to_string(), Decimal::ZERO))
+            .into();
+        let ccys: Vec<&Ccy> = opts
+            .sorted_ccys(&ccy_bal)
+            .into_iter()
+            .map(|(c, _)| c)
+            .collect();
+        assert!(ccys == ["GBP", "EUR", "USD"]);
+    }
+
+    #[test]
+    fn test_account_type() {
+        let opts = Options {
+            name_income: "Ertrag".to_string(),
+            ..Default::default()
+        };
+        assert!(opts.account_type("Assets:Bank") == Some(AccountType::Assets));
+        assert!(opts.account_type("Ertrag:Job") == Some(AccountType::Income));
+        assert!(opts.account_type("Income:Job").is_none());
+        assert!(AccountType::Equity.is_balance_sheet());
+        assert!(AccountType::Expenses.is_income_statement());
+    }
+
+    #[test]
+    fn test_strings() {
+        let text = r#"
+2023-01-01 * "Joe's" "Dinner at \"Joe's\"
+and drinks"
+  note: "a \\ b"
+  Expenses:Food  10 GBP
+  Assets:Bank
+        "#;
+        let cst = cst::parse(text);
+        let tx = Transaction::from_node(&cst.entries[0].node).unwrap();
+        assert!(tx.payee == Some("Joe's".to_string()));
+        assert!(tx.narration == "Dinner at \"Joe's\"\nand drinks");
+        assert!(tx.meta[0].val == MetaValue::Text("a \\ b".to_string()));
+        assert!(tx.postings.len() == 2);
+        let printed = tx.to_string();
+        assert!(
+            printed.starts_with("2023-01-01 * \"Joe's\" \"Dinner at \\\"Joe's\\\"\nand drinks\"")
+        );
+    }
+
+    #[test]
     fn test_bad_amount() {
         let text = r#"
             2023-01-01 price FOO 1,.0.0 BAR
         "#;
-        let mut entries = loader::load(&text);
-        let entry = entries.next().unwrap();
-        Price::from_entry(entry);
+        let cst = cst::parse(text);
+        let err = Price::from_node(&cst.entries[0].node).unwrap_err();
+        assert!(err.ty == ErrorType::InvalidNumber);
+    }
+
+    #[test]
+    fn test_amount_expression() {
+        let text = r#"
+2023-01-01 * "Split bill"
+  Expenses:Food  (120.00 / 3) GBP
+  Assets:Bank    -1,234.50 + 12 GBP
+        "#;
+        let cst = cst::parse(text);
+        let tx = Transaction::from_node(&cst.entries[0].node).unwrap();
+        let got: Vec<Decimal> = tx
+            .postings
+            .iter()
+            .map(|p| p.amount.clone().unwrap().number)
+            .collect();
+        assert!(got == vec![Decimal::new(40, 0), Decimal::new(-122250, 2)]);
     }
 }
@@ -15,10 +15,16 @@ pub enum ErrorType {
     BalanceAssertion,
     UnusedPad,
     InvalidCcy,
+    InvalidOption,
+    InvalidAccount,
+    InvalidNumber,
+    UnbalancedTrial,
+    InvalidQuery,
+    InvalidFilter,
 }
 
 #[pyclass]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct BeanError {
     pub ty: ErrorType,
     pub debug: DebugLine,
@@ -39,6 +45,10 @@ impl BeanError {
 
 impl fmt::Display for BeanError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Errors about the whole Ledger, or a query, aren't on any line
+        if self.debug.line == 0 {
+            return write!(f, "{msg}", msg = self.msg);
+        }
         write!(
             f,
             "line:{debug}:  {msg}",
@@ -0,0 +1,348 @@
+//! Filter expressions for narrowing down the Directives in a Ledger, like fava's filters
+//!
+//! A filter is a list of space-separated terms that must all match, eg
+//! `account:^Expenses:Food tag:trip date:2025`. A term starting with `-` is negated,
+//! and values containing spaces can be quoted: `payee:"Corner Shop"`.
+//!
+//! | Term                       | Matches Transactions where                       |
+//! |----------------------------|--------------------------------------------------|
+//! | `account:REGEX`            | any posting's account matches                    |
+//! | `payee:REGEX`              | the payee matches (case-insensitive)             |
+//! | `narration:REGEX`          | the narration matches (case-insensitive)         |
+//! | `REGEX`                    | the payee or narration matches                   |
+//! | `tag:NAME` or `#NAME`      | it has the tag                                   |
+//! | `link:NAME` or `^NAME`     | it has the link                                  |
+//! | `flag:!`                   | it has the flag                                  |
+//! | `currency:CCY`             | any posting is in the currency                   |
+//! | `date:2025`, `date:2025-Q1`, `date:2025-03`, `date:2025-03-01`, `date:2025-01..2025-03` | the date is in the range |
+//! | `KEY:REGEX`                | the Transaction or a posting has matching metadata |
+//!
+//! Other Directives are only filtered by `date` and `account` terms.
+
+use chrono::{Months, NaiveDate};
+use regex::{Regex, RegexBuilder};
+
+use crate::data::{DebugLine, Directive, Transaction};
+use crate::error::{BeanError, ErrorType};
+
+#[derive(Clone, Debug)]
+enum Predicate {
+    Account(Regex),
+    Payee(Regex),
+    Narration(Regex),
+    Text(Regex),
+    Tag(String),
+    Link(String),
+    Flag(String),
+    Currency(String),
+    /// From (inclusive) and to (exclusive)
+    Date(Option<NaiveDate>, Option<NaiveDate>),
+    Meta(String, Regex),
+}
+
+#[derive(Clone, Debug)]
+struct Term {
+    negated: bool,
+    predicate: Predicate,
+}
+
+/// A parsed filter expression
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    terms: Vec<Term>,
+}
+
+fn invalid(msg: &str) -> BeanError {
+    let msg = format!("Invalid filter: {msg}");
+    BeanError::new(ErrorType::InvalidFilter, &DebugLine::default(), &msg, None)
+}
+
+fn regex(pattern: &str, case_insensitive: bool) -> Result<Regex, BeanError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| invalid(&format!("bad regex '{pattern}': {e}")))
+}
+
+/// Split on whitespace, except inside double quotes, which are removed
+fn tokenize(text: &str) -> Result<Vec<String>, BeanError> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if quoted {
+        return Err(invalid(&format!("unclosed quote in: {text}")));
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// The first day of the year, quarter, month or day given, and the first day after it
+fn date_range(text: &str) -> Option<(NaiveDate, NaiveDate)> {
+    if let Some((y, q)) = text.split_once("-Q") {
+        let month = 3 * q.parse::<u32>().ok().filter(|q| (1..=4).contains(q))? - 2;
+        let start = NaiveDate::from_ymd_opt(y.parse().ok()?, month, 1)?;
+        return Some((start, start.checked_add_months(Months::new(3))?));
+    }
+    let parts: Vec<&str> = text.split('-').collect();
+    let numbers: Vec<u32> = parts
+        .iter()
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [y] if parts[0].len() == 4 => {
+            let start = NaiveDate::from_ymd_opt(y as i32, 1, 1)?;
+            Some((start, start.checked_add_months(Months::new(12))?))
+        }
+        [y, m] => {
+            let start = NaiveDate::from_ymd_opt(y as i32, m, 1)?;
+            Some((start, start.checked_add_months(Months::new(1))?))
+        }
+        [y, m, d] => {
+            let start = NaiveDate::from_ymd_opt(y as i32, m, d)?;
+            Some((start, start.succ_opt()?))
+        }
+        _ => None,
+    }
+}
+
+/// The start (inclusive) and end (exclusive) of a period like `2025`, `2025-Q1`,
+/// `2025-03`, `2025-03-01` or a range of them like `2025-01..2025-03`, `2025..` or `..2025`
+pub fn parse_period(text: &str) -> Result<(Option<NaiveDate>, Option<NaiveDate>), BeanError> {
+    let bad = || {
+        invalid(&format!(
+            "bad date '{text}', use eg 2025, 2025-Q1, 2025-03 or 2025-01..2025-03"
+        ))
+    };
+    let range = |part: &str| match part {
+        "" => Ok(None),
+        part => date_range(part).map(Some).ok_or_else(bad),
+    };
+    match text.split_once("..") {
+        Some((from, to)) => {
+            let from = range(from)?.map(|(start, _)| start);
+            let to = range(to)?.map(|(_, end)| end);
+            Ok((from, to))
+        }
+        None => {
+            let (from, to) = date_range(text).ok_or_else(bad)?;
+            Ok((Some(from), Some(to)))
+        }
+    }
+}
+
+fn parse_term(token: &str) -> Result<Term, BeanError> {
+    let (negated, token) = match token.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, token),
+    };
+    let predicate = if let Some(tag) = token.strip_prefix('#') {
+        Predicate::Tag(tag.to_string())
+    } else if let Some(link) = token.strip_prefix('^').filter(|_| !token.contains(':')) {
+        Predicate::Link(link.to_string())
+    } else {
+        match token.split_once(':') {
+            Some(("account", v)) => Predicate::Account(regex(v, false)?),
+            Some(("payee", v)) => Predicate::Payee(regex(v, true)?),
+            Some(("narration", v)) => Predicate::Narration(regex(v, true)?),
+            Some(("tag", v)) => Predicate::Tag(v.to_string()),
+            Some(("link", v)) => Predicate::Link(v.to_string()),
+            Some(("flag", v)) => Predicate::Flag(v.to_string()),
+            Some(("currency", v)) => Predicate::Currency(v.to_string()),
+            Some(("date", v)) => {
+                let (from, to) = parse_period(v)?;
+                Predicate::Date(from, to)
+            }
+            Some((key, v)) if key.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                Predicate::Meta(key.to_string(), regex(v, true)?)
+            }
+            _ => Predicate::Text(regex(token, true)?),
+        }
+    };
+    Ok(Term { negated, predicate })
+}
+
+impl Predicate {
+    /// Whether a Transaction matches
+    fn matches_tx(&self, tx: &Transaction) -> bool {
+        match self {
+            Predicate::Account(re) => tx.postings.iter().any(|p| re.is_match(&p.account)),
+            Predicate::Payee(re) => tx.payee.as_ref().is_some_and(|p| re.is_match(p)),
+            Predicate::Narration(re) => re.is_match(&tx.narration),
+            Predicate::Text(re) => {
+                re.is_match(&tx.narration) || tx.payee.as_ref().is_some_and(|p| re.is_match(p))
+            }
+            Predicate::Tag(tag) => tx.tags.iter().any(|t| t.trim_start_matches('#') == tag),
+            Predicate::Link(link) => tx.links.iter().any(|l| l.trim_start_matches('^') == link),
+            Predicate::Flag(flag) => match tx.ty.as_str() {
+                "txn" => flag == "*",
+                ty => ty == flag,
+            },
+            Predicate::Currency(ccy) => tx
+                .postings
+                .iter()
+                .any(|p| p.amount.as_ref().is_some_and(|a| &a.ccy == ccy)),
+            Predicate::Date(..) => self.matches_date(&tx.date),
+            Predicate::Meta(key, re) => tx
+                .meta
+                .iter()
+                .chain(tx.postings.iter().flat_map(|p| &p.meta))
+                .any(|m| &m.key == key && re.is_match(m.val.as_str())),
+        }
+    }
+
+    fn matches_date(&self, date: &NaiveDate) -> bool {
+        match self {
+            Predicate::Date(from, to) => {
+                from.is_none_or(|from| *date >= from) && to.is_none_or(|to| *date < to)
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether any other Directive matches, or None if the predicate doesn't apply to it
+    fn matches_other(&self, d: &Directive) -> Option<bool> {
+        match self {
+            Predicate::Date(..) => Some(self.matches_date(d.date())),
+            Predicate::Account(re) => {
+                let accounts = d.accounts();
+                match accounts.is_empty() {
+                    true => None,
+                    false => Some(accounts.iter().any(|a| re.is_match(a))),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Filter {
+    /// Parse a filter expression, an empty one matches everything
+    pub fn parse(text: &str) -> Result<Self, BeanError> {
+        let terms = tokenize(text)?
+            .iter()
+            .map(|t| parse_term(t))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { terms })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether the Directive passes every term
+    pub fn matches(&self, d: &Directive) -> bool {
+        self.terms.iter().all(|term| {
+            let matched = match d {
+                Directive::Transaction(tx) => Some(term.predicate.matches_tx(tx)),
+                d => term.predicate.matches_other(d),
+            };
+            // A term that doesn't apply to a Directive never excludes it
+            matched.is_none_or(|m| m != term.negated)
+        })
+    }
+
+    /// The Directives that pass the filter
+    pub fn apply(&self, dirs: &[Directive]) -> Vec<Directive> {
+        dirs.iter().filter(|d| self.matches(d)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"
+2025-01-01 open Assets:Bank
+2025-01-01 open Expenses:Food
+2025-01-01 open Expenses:Travel
+
+2025-02-01 * "Corner Shop" "Groceries"
+  Expenses:Food     20 GBP
+  Assets:Bank
+
+2025-03-01 ! "Flights" #trip ^booking
+  trip: "Rome"
+  Expenses:Travel   200 EUR
+  Assets:Bank      -200 EUR
+
+2025-03-02 * "Dinner in Rome" #trip
+  Expenses:Food     30 EUR
+  Assets:Bank      -30 EUR
+
+2026-01-01 balance Assets:Bank  -230 EUR
+"#;
+
+    /// The narrations of the Transactions, and the number of other Directives, that pass
+    fn run(filter: &str) -> (Vec<String>, usize) {
+        let ledger = crate::load(TEXT.to_string());
+        let dirs = Filter::parse(filter).unwrap().apply(&ledger.dirs);
+        let narrations = dirs
+            .iter()
+            .filter_map(|d| match d {
+                Directive::Transaction(tx) => Some(tx.narration.clone()),
+                _ => None,
+            })
+            .collect();
+        let others = dirs
+            .iter()
+            .filter(|d| !matches!(d, Directive::Transaction(_)))
+            .count();
+        (narrations, others)
+    }
+
+    #[test]
+    fn test_filter() {
+        assert!(
+            run("")
+                == (
+                    vec![
+                        "Groceries".into(),
+                        "Flights".into(),
+                        "Dinner in Rome".into()
+                    ],
+                    4
+                )
+        );
+        assert!(run("account:^Expenses:Food").0 == vec!["Groceries", "Dinner in Rome"]);
+        assert!(run("account:^Expenses:Food").1 == 1);
+        assert!(run("tag:trip -#trip").0.is_empty());
+        assert!(run("#trip currency:EUR flag:*").0 == vec!["Dinner in Rome"]);
+        assert!(run("^booking").0 == vec!["Flights"]);
+        assert!(run("payee:\"corner shop\"").0 == vec!["Groceries"]);
+        assert!(run("rome").0 == vec!["Dinner in Rome"]);
+        assert!(run("trip:rome").0 == vec!["Flights"]);
+        assert!(run("date:2025-03").0 == vec!["Flights", "Dinner in Rome"]);
+        assert!(run("date:2025-03").1 == 0);
+        assert!(run("date:2025-02..2025-03-01").0 == vec!["Groceries", "Flights"]);
+        assert!(run("date:..2025").1 == 3);
+        assert!(run("date:2025-Q1").0.len() == 3);
+        assert!(run("date:2025-Q2").0.is_empty());
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        for text in [
+            "account:(",
+            "date:2025-13",
+            "date:2025-Q5",
+            "date:25",
+            "payee:\"open",
+        ] {
+            let err = Filter::parse(text).unwrap_err();
+            assert!(err.ty == ErrorType::InvalidFilter, "{text}");
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Format beancount text in the style of `bean-format`
+//!
+//! This works on the concrete syntax tree (see `cst`) rather than on the
+//! loaded Directives, so that comments, headings, blank lines and line
+//! endings are all kept. Indentation is normalised and posting, balance
+//! and price amounts are aligned so that their currencies line up in one column.
+
+use crate::cst::{self, Element, Node, TokenKind};
+use crate::grammar::Rule;
+
+pub const DEFAULT_CURRENCY_COLUMN: usize = 52;
+
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    /// The (zero-based) column that currencies should start at
+    pub currency_column: usize,
+    /// Number of spaces to indent postings and metadata
+    pub indent: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            currency_column: DEFAULT_CURRENCY_COLUMN,
+            indent: 2,
+        }
+    }
+}
+
+/// A Token or leaf Node, with the kinds of the Nodes it is inside (outermost first)
+struct Leaf<'i> {
+    text: &'i str,
+    token: Option<TokenKind>,
+    path: Vec<Rule>,
+}
+
+impl Leaf<'_> {
+    fn is_whitespace(&self) -> bool {
+        self.token == Some(TokenKind::Whitespace)
+    }
+
+    fn inside(&self, kind: Rule) -> bool {
+        self.path.contains(&kind)
+    }
+}
+
+/// The Tokens and leaf Nodes of a line, and the newline that ends it (if any)
+struct Line<'i> {
+    leaves: Vec<Leaf<'i>>,
+    newline: &'i str,
+}
+
+fn push_leaves<'i>(node: &Node<'i>, path: &mut Vec<Rule>, out: &mut Vec<Leaf<'i>>) {
+    path.push(node.kind);
+    if node.children.is_empty() {
+        out.push(Leaf {
+            text: node.leaf,
+            token: None,
+            path: path.clone(),
+        });
+    }
+    for child in &node.children {
+        match child {
+            Element::Node(n) => push_leaves(n, path, out),
+            Element::Token(t) => out.push(Leaf {
+                text: t.text,
+                token: Some(t.kind),
+                path: path.clone(),
+            }),
+        }
+    }
+    path.pop();
+}
+
+fn push_trivia<'i>(tokens: &[cst::Token<'i>], out: &mut Vec<Leaf<'i>>) {
+    out.extend(tokens.iter().map(|t| Leaf {
+        text: t.text,
+        token: Some(t.kind),
+        path: Vec::new(),
+    }))
+}
+
+/// Split the text into lines of leaves, keeping the original newlines
+fn lines(text: &str) -> Vec<Line<'_>> {
+    let cst = cst::parse(text);
+    let mut leaves: Vec<Leaf> = Vec::new();
+    for entry in &cst.entries {
+        push_trivia(&entry.leading, &mut leaves);
+        push_leaves(&entry.node, &mut Vec::new(), &mut leaves);
+        push_trivia(&entry.trailing, &mut leaves);
+    }
+    push_trivia(&cst.trailing, &mut leaves);
+
+    let mut lines = vec![Line {
+        leaves: Vec::new(),
+        newline: "",
+    }];
+    for leaf in leaves {
+        if leaf.token == Some(TokenKind::Newline) {
+            lines.last_mut().unwrap().newline = leaf.text;
+            lines.push(Line {
+                leaves: Vec::new(),
+                newline: "",
+            });
+        } else {
+            lines.last_mut().unwrap().leaves.push(leaf);
+        }
+    }
+    lines
+}
+
+/// Join the leaves' text as it was in the original
+fn join(leaves: &[Leaf]) -> String {
+    leaves.iter().map(|l| l.text).collect()
+}
+
+/// Put the amount in `leaves` (if any) after `prefix`, so that
+/// the currency starts at `opts.currency_column`
+fn align_amount(prefix: &str, leaves: &[Leaf], opts: &FormatOptions) -> Option<String> {
+    let start = leaves.iter().position(|l| l.inside(Rule::amount))?;
+    let end = start
+        + leaves[start..]
+            .iter()
+            .take_while(|l| l.inside(Rule::amount))
+            .count();
+    let amount = &leaves[start..end];
+    let number: String = amount
+        .iter()
+        .filter(|l| l.inside(Rule::num_expr))
+        .map(|l| l.text)
+        .collect();
+    let ccy = amount.iter().find(|l| l.inside(Rule::ccy))?.text;
+    let after = join(&leaves[end..]);
+    let used = prefix.chars().count() + number.chars().count() + 1;
+    let pad = opts.currency_column.saturating_sub(used).max(2);
+    Some(format!(
+        "{prefix}{pad}{number} {ccy}{after}",
+        pad = " ".repeat(pad),
+    ))
+}
+
+/// Format one line, leaving alone the text of every leaf
+fn format_line(leaves: &[Leaf], opts: &FormatOptions) -> String {
+    let Some(i) = leaves.iter().position(|l| !l.is_whitespace()) else {
+        return String::new();
+    };
+    let first = &leaves[i];
+    let text = join(leaves);
+    let indent = " ".repeat(opts.indent);
+    if first.token == Some(TokenKind::Comment) {
+        // Comments are only indented if they were already, or are within an entry
+        return match first.path.is_empty() && !leaves[0].is_whitespace() {
+            true => text,
+            false => format!("{indent}{}", text.trim_start()),
+        };
+    }
+    if first.inside(Rule::metadata) {
+        // Metadata under a posting gets an extra level of indentation
+        let indent = match first.inside(Rule::posting) {
+            true => indent.repeat(2),
+            false => indent,
+        };
+        return format!("{indent}{}", text.trim_start());
+    }
+    if first.inside(Rule::posting) {
+        let prefix = format!("{indent}{}", first.text);
+        return align_amount(&prefix, &leaves[i + 1..], opts)
+            .unwrap_or_else(|| format!("{indent}{}", text.trim_start()));
+    }
+    if matches!(first.path.first(), Some(Rule::balance | Rule::price)) && first.inside(Rule::date) {
+        // The words before the amount are joined with single spaces
+        let start = leaves.iter().position(|l| l.inside(Rule::amount));
+        let words: Vec<&str> = leaves[..start.unwrap_or(0)]
+            .iter()
+            .filter(|l| !l.is_whitespace())
+            .map(|l| l.text)
+            .collect();
+        if let Some(line) = align_amount(&words.join(" "), leaves, opts) {
+            return line;
+        }
+    }
+    text
+}
+
+/// Format the whole text, keeping everything that isn't whitespace
+pub fn format(text: &str, opts: &FormatOptions) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in lines(text) {
+        out.push_str(format_line(&line.leaves, opts).trim_end_matches([' ', '\t']));
+        out.push_str(line.newline);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let text = "* Heading
+option \"title\" \"x\"
+
+; A comment
+2023-01-01 price GOOG    50 GBP
+2023-02-01 * \"Salary\" ; comment
+    user: \"Chris\"
+\tAssets:Bank   1,000.00 GBP ; trailing
+        check: TRUE
+  ; comment
+ Income:Job
+2023-03-03 balance Assets:Bank 860 GBP
+";
+        let opts = FormatOptions {
+            currency_column: 30,
+            indent: 2,
+        };
+        let want = "* Heading
+option \"title\" \"x\"
+
+; A comment
+2023-01-01 price GOOG      50 GBP
+2023-02-01 * \"Salary\" ; comment
+  user: \"Chris\"
+  Assets:Bank        1,000.00 GBP ; trailing
+    check: TRUE
+  ; comment
+  Income:Job
+2023-03-03 balance Assets:Bank  860 GBP
+";
+        let got = format(text, &opts);
+        assert_eq!(got, want);
+        assert_eq!(format(&got, &opts), got);
+    }
+
+    #[test]
+    fn test_format_expressions_and_strings() {
+        let text = "2023-01-03 * \"A narration
+    that spans lines  \"
+   Expenses:Food (120.00 / 3) GBP {1 USD} @ 2 USD
+  Assets:Bank
+";
+        let opts = FormatOptions {
+            currency_column: 34,
+            indent: 2,
+        };
+        let want = "2023-01-03 * \"A narration
+    that spans lines  \"
+  Expenses:Food      (120.00 / 3) GBP {1 USD} @ 2 USD
+  Assets:Bank
+";
+        assert_eq!(format(text, &opts), want);
+    }
+
+    #[test]
+    fn test_format_crlf() {
+        let text = "; A comment\r\n2023-02-01 * \"Salary\"\r\n    Assets:Bank 1000 GBP  \r\n Income:Job\r\n";
+        let opts = FormatOptions {
+            currency_column: 30,
+            indent: 2,
+        };
+        let want = "; A comment\r\n2023-02-01 * \"Salary\"\r\n  Assets:Bank            1000 GBP\r\n  Income:Job\r\n";
+        let got = format(text, &opts);
+        assert_eq!(got, want);
+        assert_eq!(format(&got, &opts), got);
+    }
+}
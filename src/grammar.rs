@@ -3,3 +3,74 @@ use pest_derive::Parser;
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct BeanParser;
+
+#[cfg(test)]
+mod tests {
+    use pest::Parser;
+
+    use super::*;
+
+    /// Whether the whole of `text` is consumed by `rule`
+    fn full_match(rule: Rule, text: &str) -> bool {
+        match BeanParser::parse(rule, text) {
+            Ok(mut pairs) => pairs.next().unwrap().as_str() == text,
+            Err(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_account() {
+        let good = [
+            "Assets:Bank",
+            "Assets:US:Vanguard:VTI",
+            "Assets:Bank:1stDirect",
+            "Expenses:Café",
+            "Liabilities:Credit-Card",
+            "Aktiva:Girokonto",
+            "Ärzte:Zahnarzt",
+            "Assets:B",
+        ];
+        for acc in good {
+            assert!(full_match(Rule::account, acc), "{acc}");
+        }
+        let bad = [
+            "Assets",
+            "assets:Bank",
+            "Assets:bank",
+            "Assets:-Bank",
+            "Assets::Bank",
+        ];
+        for acc in bad {
+            assert!(!full_match(Rule::account, acc), "{acc}");
+        }
+    }
+
+    #[test]
+    fn test_ccy() {
+        let good = [
+            "GBP",
+            "VBTLX",
+            "BRK.B",
+            "A-SHARE",
+            "VT",
+            "HOOL_2",
+            "NT'L",
+            "ABCDEFGHIJKLMNOPQRSTUVWX",
+        ];
+        for ccy in good {
+            assert!(full_match(Rule::ccy, ccy), "{ccy}");
+        }
+        let bad = ["G", "gbp", "1GBP", "GBP-", "ABCDEFGHIJKLMNOPQRSTUVWXY"];
+        for ccy in bad {
+            assert!(!full_match(Rule::ccy, ccy), "{ccy}");
+        }
+    }
+
+    #[test]
+    fn test_posting() {
+        let text = "  Assets:US:Vanguard:VTI  10 VBTLX";
+        assert!(full_match(Rule::posting, text));
+        let text = "  Expenses:Café  4.50 BRK.B";
+        assert!(full_match(Rule::posting, text));
+    }
+}
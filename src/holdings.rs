@@ -0,0 +1,487 @@
+//! Holdings: what is held in each account, at cost and at market value
+//!
+//! Lots are booked in an `Inventory` per account and commodity, so a reduction
+//! (a sale) comes out of the lot its `{cost}` names, or else the oldest lots first.
+//! An acquisition costs its `{cost}` or else its `@ price`; a commodity held in the
+//! currency it is valued in (cash) costs its own amount.
+//!
+//! `unrealized_gains` books the difference between market value and book value,
+//! so that balances reflect market moves.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDate;
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::data::{Account, AccountType, Amount, Directive, Options, Posting, Transaction};
+use crate::inventory::{Inventory, Position};
+use crate::ledger::Ledger;
+use crate::loader;
+use crate::period;
+use crate::prices::PriceMap;
+use crate::query::{Table, Value};
+
+/// The units of one commodity held in an account
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holding {
+    pub account: Account,
+    pub units: Amount,
+    /// The total cost, if every acquisition had one in the same currency
+    pub book_value: Option<Amount>,
+    /// Whether every acquisition had a `{cost}`, rather than just a price
+    pub at_cost: bool,
+    /// The latest price of one unit, in the currency of the book value
+    /// or else the operating currency
+    pub price: Option<Amount>,
+}
+
+impl Holding {
+    pub fn average_cost(&self) -> Option<Amount> {
+        let book = self.book_value.as_ref()?;
+        if self.units.number.is_zero() {
+            return None;
+        }
+        let number = (book.number / self.units.number).round_dp(4).normalize();
+        Some(Amount::new(number, book.ccy.clone()))
+    }
+
+    pub fn market_value(&self) -> Option<Amount> {
+        let price = self.price.as_ref()?;
+        Some(Amount::new(
+            self.units.number * price.number,
+            price.ccy.clone(),
+        ))
+    }
+
+    /// Market value less book value, if both are known in the same currency
+    pub fn gain(&self) -> Option<Amount> {
+        let (market, book) = (self.market_value()?, self.book_value.as_ref()?);
+        if market.ccy != book.ccy {
+            return None;
+        }
+        Some(Amount::new(market.number - book.number, market.ccy))
+    }
+
+    /// The gain as a percentage of the book value
+    pub fn gain_pct(&self) -> Option<Decimal> {
+        let book = self.book_value.as_ref()?;
+        if book.number.is_zero() {
+            return None;
+        }
+        Some((self.gain()?.number / book.number * Decimal::ONE_HUNDRED).round_dp(2))
+    }
+}
+
+/// The cost of one unit from a Posting's cost, or else its price
+fn unit_cost(posting: &Posting) -> Option<Amount> {
+    if let Some(cost) = posting.cost.as_ref().and_then(|c| c.amount.clone()) {
+        return Some(cost);
+    }
+    let price = posting.price.as_ref()?;
+    Some(Amount::new(price.number?, price.ccy.clone()))
+}
+
+/// The total units in an Inventory of one commodity
+fn units(inv: &Inventory) -> Decimal {
+    inv.positions().iter().map(|p| p.units.number).sum()
+}
+
+/// The part of two account names that they share, eg `Assets` for `Assets:A` and `Assets:B`
+fn common_parent(a: &str, b: &str) -> Account {
+    let shared: Vec<&str> = a
+        .split(':')
+        .zip(b.split(':'))
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect();
+    shared.join(":")
+}
+
+/// Every commodity held, in account order
+#[derive(Clone, Debug, Default)]
+pub struct Holdings {
+    pub rows: Vec<Holding>,
+}
+
+impl Holdings {
+    /// The holdings of accounts matching `accounts`, or of all Assets,
+    /// priced at the latest prices on or before `date`
+    pub fn new(
+        dirs: &[Directive],
+        accounts: Option<&Regex>,
+        prices: &PriceMap,
+        date: NaiveDate,
+        opts: &Options,
+    ) -> Self {
+        let included = |account: &str| match accounts {
+            Some(re) => re.is_match(account),
+            None => opts.account_type(account) == Some(AccountType::Assets),
+        };
+        let mut lots: BTreeMap<(Account, String), Inventory> = BTreeMap::new();
+        let mut not_at_cost: BTreeSet<(Account, String)> = BTreeSet::new();
+        for d in dirs {
+            let Directive::Transaction(tx) = d else {
+                continue;
+            };
+            for p in tx.postings.iter().filter(|p| included(&p.account)) {
+                if let Some(amount) = &p.amount {
+                    let key = (p.account.clone(), amount.ccy.clone());
+                    let inv = lots.entry(key.clone()).or_default();
+                    let held = units(inv);
+                    let reduces = !held.is_zero()
+                        && held.is_sign_positive() != amount.number.is_sign_positive();
+                    // A reduction's price is what it was sold for, not what it cost
+                    let cost = match reduces {
+                        true => p.cost.as_ref().and_then(|c| c.amount.clone()),
+                        false => unit_cost(p),
+                    };
+                    inv.add(&Position::new(amount.clone(), cost));
+                    if !reduces && p.cost.as_ref().is_none_or(|c| c.amount.is_none()) {
+                        not_at_cost.insert(key);
+                    }
+                }
+            }
+        }
+
+        let mut rows: Vec<Holding> = Vec::new();
+        for (key, inv) in lots {
+            let at_cost = !not_at_cost.contains(&key);
+            let (account, commodity) = key;
+            let units = units(&inv);
+            if units.is_zero() {
+                continue;
+            }
+            let positions = inv.positions();
+            let ccy = match positions.iter().find_map(|p| p.cost.as_ref()) {
+                Some(cost) => Some(cost.ccy.clone()),
+                None if !opts.operating_currency.is_empty() => {
+                    Some(opts.operating_currency.clone())
+                }
+                None => None,
+            };
+            let costed = |ccy: &str| {
+                positions
+                    .iter()
+                    .all(|p| p.cost.as_ref().is_some_and(|c| c.ccy == ccy))
+            };
+            let book_value = match &ccy {
+                Some(ccy) if *ccy == commodity => Some(Amount::new(units, ccy.clone())),
+                Some(ccy) if costed(ccy) => {
+                    let number = positions.iter().map(|p| p.cost().number).sum();
+                    Some(Amount::new(number, ccy.clone()))
+                }
+                _ => None,
+            };
+            let price = ccy.and_then(|ccy| {
+                let rate = prices.rate(&commodity, &ccy, date)?;
+                Some(Amount::new(rate, ccy))
+            });
+            rows.push(Holding {
+                account,
+                units: Amount::new(units, commodity),
+                book_value,
+                at_cost,
+                price,
+            });
+        }
+        rows.sort_by(|a, b| {
+            (opts.account_order(&a.account), opts.ccy_order(&a.units.ccy))
+                .cmp(&(opts.account_order(&b.account), opts.ccy_order(&b.units.ccy)))
+        });
+        Self { rows }
+    }
+
+    /// Each commodity's holdings combined across accounts, under their common parent
+    pub fn by_commodity(&self) -> Self {
+        let mut rows: Vec<Holding> = Vec::new();
+        for h in &self.rows {
+            let price_ccy = |h: &Holding| h.price.as_ref().map(|p| p.ccy.clone());
+            let same = rows
+                .iter_mut()
+                .find(|r| r.units.ccy == h.units.ccy && price_ccy(r) == price_ccy(h));
+            let Some(row) = same else {
+                rows.push(h.clone());
+                continue;
+            };
+            row.account = common_parent(&row.account, &h.account);
+            row.units.number += h.units.number;
+            row.book_value = match (&row.book_value, &h.book_value) {
+                (Some(a), Some(b)) if a.ccy == b.ccy => {
+                    Some(Amount::new(a.number + b.number, a.ccy.clone()))
+                }
+                _ => None,
+            };
+            row.at_cost &= h.at_cost;
+        }
+        Self { rows }
+    }
+
+    /// Each holding's share of the total market value in its currency, as a percentage
+    pub fn weights(&self) -> Vec<Option<Decimal>> {
+        let mut totals: BTreeMap<String, Decimal> = BTreeMap::new();
+        for value in self.rows.iter().filter_map(Holding::market_value) {
+            *totals.entry(value.ccy).or_default() += value.number;
+        }
+        self.rows
+            .iter()
+            .map(|h| {
+                let value = h.market_value()?;
+                let total = totals[&value.ccy];
+                match total.is_zero() {
+                    true => None,
+                    false => Some((value.number / total * Decimal::ONE_HUNDRED).round_dp(2)),
+                }
+            })
+            .collect()
+    }
+
+    pub fn table(&self) -> Table {
+        let columns = [
+            "Account",
+            "Units",
+            "Average Cost",
+            "Book Value",
+            "Price",
+            "Market Value",
+            "Gain",
+            "Gain %",
+            "Weight %",
+        ];
+        let amount = |a: Option<Amount>| a.map_or(Value::Null, Value::Amount);
+        let number = |n: Option<Decimal>| n.map_or(Value::Null, Value::Number);
+        let rows = self
+            .rows
+            .iter()
+            .zip(self.weights())
+            .map(|(h, weight)| {
+                vec![
+                    Value::Str(h.account.clone()),
+                    Value::Amount(h.units.clone()),
+                    amount(h.average_cost()),
+                    amount(h.book_value.clone()),
+                    amount(h.price.clone()),
+                    amount(h.market_value()),
+                    amount(h.gain()),
+                    number(h.gain_pct()),
+                    number(weight),
+                ]
+            })
+            .collect();
+        Table {
+            columns: columns.map(String::from).to_vec(),
+            rows,
+        }
+    }
+}
+
+/// Transactions on `date` that book the unrealized gain (or loss) of each holding
+/// at cost with a price, like beancount's `unrealized` plugin
+/// Holdings bought with only an `@ price` are not held at cost, so are left out.
+/// The gain goes to the holding's account and is taken from the Income account
+/// with the same name, eg `Income:Broker:ISA` for `Assets:Broker:ISA`, or to
+/// `subaccount` of each if given. They are flagged `U` so they can be filtered out.
+pub fn unrealized_gains(
+    dirs: &[Directive],
+    date: NaiveDate,
+    subaccount: Option<&str>,
+    opts: &Options,
+) -> Vec<Directive> {
+    let held: Vec<Directive> = dirs.iter().filter(|d| *d.date() <= date).cloned().collect();
+    let prices = PriceMap::new(&held);
+    let holdings = Holdings::new(&held, None, &prices, date, opts);
+    let mut txs: Vec<Directive> = Vec::new();
+    for h in &holdings.rows {
+        let (Some(gain), Some(price), Some(cost)) = (h.gain(), &h.price, h.average_cost()) else {
+            continue;
+        };
+        if !h.at_cost || gain.number.is_zero() {
+            continue;
+        }
+        let (_, rest) = h.account.split_once(':').unwrap_or((&h.account, ""));
+        let mut asset = h.account.clone();
+        let mut income = [opts.name_income.as_str(), rest].join(":");
+        if let Some(subaccount) = subaccount {
+            asset = format!("{asset}:{subaccount}");
+            income = format!("{income}:{subaccount}");
+        }
+        let kind = match gain.number.is_sign_positive() {
+            true => "gain",
+            false => "loss",
+        };
+        let narration = format!(
+            "Unrealized {kind} for {units} (price: {price} as of {date}, average cost: {cost})",
+            units = h.units,
+        );
+        let postings = vec![
+            Posting::new(asset, gain.number, gain.ccy.clone()),
+            Posting::new(income, -gain.number, gain.ccy),
+        ];
+        txs.push(Directive::Transaction(Transaction::new(
+            date, "U", narration, postings,
+        )));
+    }
+    txs
+}
+
+/// The Ledger with the `unrealized_gains` on `date`, and Opens for their accounts if needed
+pub fn add_unrealized(ledger: &Ledger, date: NaiveDate, subaccount: Option<&str>) -> Ledger {
+    let txs = unrealized_gains(&ledger.dirs, date, subaccount, &ledger.opts);
+    let mut accounts: Vec<String> = txs.iter().flat_map(|d| d.accounts()).cloned().collect();
+    accounts.sort();
+    accounts.dedup();
+    let mut dirs = ledger.dirs.clone();
+    dirs.extend(period::open_missing(&accounts, date, &txs, ledger));
+    dirs.extend(txs);
+    loader::sort(&mut dirs);
+    Ledger {
+        dirs,
+        errs: ledger.errs.clone(),
+        opts: ledger.opts.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::CcyBal;
+
+    const TEXT: &str = r#"
+option "operating_currency" "GBP"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Broker:ISA
+2023-01-01 open Assets:Broker:GIA
+2023-01-01 open Income:Gains
+
+2023-01-01 * "Deposit"
+  Assets:Bank          1000 GBP
+  Income:Gains
+
+2023-01-10 * "Buy"
+  Assets:Broker:ISA     10 GOOG {50 GBP}
+  Assets:Bank         -500 GBP
+
+2023-01-20 * "Buy"
+  Assets:Broker:ISA     10 GOOG {60 GBP}
+  Assets:Bank         -600 GBP
+
+2023-02-01 * "Sell"
+  Assets:Broker:ISA     -5 GOOG {} @ 70 GBP
+  Assets:Bank          350 GBP
+  Income:Gains        -100 GBP
+
+2023-02-10 * "Buy"
+  Assets:Broker:GIA      4 GOOG @ 65 GBP
+  Assets:Bank         -260 GBP
+
+2023-03-01 price GOOG   80 GBP
+"#;
+
+    fn gbp(n: i64, scale: u32) -> Option<Amount> {
+        Some(Amount::new(Decimal::new(n, scale), "GBP".to_string()))
+    }
+
+    #[test]
+    fn test_holdings() {
+        let ledger = crate::load(TEXT.to_string());
+        let prices = PriceMap::new(&ledger.dirs);
+        let date = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
+        let holdings = Holdings::new(&ledger.dirs, None, &prices, date, &ledger.opts);
+        let accounts: Vec<&str> = holdings.rows.iter().map(|h| h.account.as_str()).collect();
+        assert!(accounts == ["Assets:Bank", "Assets:Broker:GIA", "Assets:Broker:ISA"]);
+
+        let bank = &holdings.rows[0];
+        assert!(bank.book_value == gbp(-10, 0));
+        assert!(bank.gain() == gbp(0, 0));
+        assert!(!bank.at_cost && !holdings.rows[1].at_cost);
+
+        let isa = &holdings.rows[2];
+        assert!(isa.units.number == Decimal::new(15, 0));
+        assert!(isa.at_cost);
+        // The sale came out of the lot at 50 GBP
+        assert!(isa.book_value == gbp(850, 0));
+        assert!(isa.average_cost() == gbp(566667, 4));
+        assert!(isa.market_value() == gbp(1200, 0));
+        assert!(isa.gain() == gbp(350, 0));
+        assert!(isa.gain_pct() == Some(Decimal::new(4118, 2)));
+
+        let weights = holdings.weights();
+        assert!(weights[2] == Some(Decimal::new(7947, 2)));
+
+        let total = holdings.by_commodity();
+        assert!(total.rows.len() == 2);
+        assert!(total.rows[1].account == "Assets:Broker");
+        assert!(total.rows[1].units.number == Decimal::new(19, 0));
+        assert!(total.rows[1].book_value == gbp(1110, 0));
+
+        // Before there was a price
+        let date = NaiveDate::from_ymd_opt(2023, 2, 28).unwrap();
+        let re = Regex::new("Assets:Broker:.*").unwrap();
+        let holdings = Holdings::new(&ledger.dirs, Some(&re), &prices, date, &ledger.opts);
+        assert!(holdings.rows.len() == 2);
+        assert!(holdings.rows[0].market_value().is_none());
+        let table = holdings.table();
+        assert!(table.rows[0][6] == Value::Null);
+        assert!(table.rows[0][3] == Value::Amount(gbp(260, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_unrealized_gains() {
+        let ledger = crate::load(TEXT.to_string());
+        let date = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
+        let txs = unrealized_gains(&ledger.dirs, date, None, &ledger.opts);
+        assert!(txs.len() == 1);
+        let Directive::Transaction(tx) = &txs[0] else {
+            panic!("Expected a Transaction");
+        };
+        assert!(tx.ty == "U");
+        assert!(tx
+            .narration
+            .starts_with("Unrealized gain for 15 GOOG (price: 80 GBP"));
+        assert!(tx.postings[0].account == "Assets:Broker:ISA");
+        assert!(tx.postings[1].account == "Income:Broker:ISA");
+        assert!(tx.postings[1].amount == gbp(-350, 0));
+
+        // No price yet
+        let date = NaiveDate::from_ymd_opt(2023, 2, 28).unwrap();
+        assert!(unrealized_gains(&ledger.dirs, date, None, &ledger.opts).is_empty());
+
+        let date = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
+        let mut ledger = add_unrealized(&ledger, date, Some("Unrealized"));
+        let (bals, errs) = crate::book::get_balances(&mut ledger.dirs);
+        assert!(errs.is_empty());
+        assert!(bals["Assets:Broker:ISA:Unrealized"]["GBP"] == Decimal::new(350, 0));
+        assert!(!bals.contains_key("Income:Broker:GIA:Unrealized"));
+    }
+
+    #[test]
+    fn test_unrealized_gains_blank_legs() {
+        let text = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Broker
+2023-01-01 open Income:Gains
+2023-01-10 * "Buy"
+  Assets:Broker   10 GOOG {50 GBP}
+  Assets:Bank
+2023-02-01 * "Sell"
+  Assets:Broker   -5 GOOG {} @ 70 GBP
+  Assets:Bank    350 GBP
+  Income:Gains
+2023-03-01 price GOOG   80 GBP
+"#;
+        let ledger = crate::load(text.to_string());
+        let date = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
+        let txs = unrealized_gains(&ledger.dirs, date, None, &ledger.opts);
+        assert!(txs.len() == 1);
+        assert!(txs[0].accounts().contains(&&"Assets:Broker".to_string()));
+
+        let mut ledger = add_unrealized(&ledger, date, None);
+        let (bals, errs) = crate::book::get_balances(&mut ledger.dirs);
+        assert!(errs.is_empty());
+        let gbp = |n: i64| CcyBal::from([("GBP".to_string(), Decimal::new(n, 0))]);
+        assert!(bals["Assets:Bank"] == gbp(-150));
+        assert!(bals["Income:Gains"] == gbp(-100));
+        // Only the 5 GOOG left at 50 GBP are revalued at 80 GBP
+        assert!(bals["Income:Broker"]["GBP"] == Decimal::new(-150, 0));
+    }
+}
@@ -0,0 +1,237 @@
+//! Positions and inventories, like beancount's `position` and `inventory`
+//!
+//! A Position is an amount of a commodity, optionally held at a per-unit cost,
+//! and an Inventory sums them while keeping lots at different costs apart.
+//! A reduction with no cost, such as a sale posted as `-5 GOOG {}`, is booked
+//! against the lots held at cost first in, first out.
+
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::data::{Amount, Posting};
+
+/// Units of a commodity, with the per-unit cost if held at cost
+#[derive(Clone, Debug, PartialEq)]
+pub struct Position {
+    pub units: Amount,
+    pub cost: Option<Amount>,
+}
+
+impl Position {
+    pub fn new(units: Amount, cost: Option<Amount>) -> Self {
+        Self { units, cost }
+    }
+
+    /// The position added by a Posting, if its amount is known
+    pub fn from_posting(posting: &Posting) -> Option<Self> {
+        let units = posting.amount.clone()?;
+        let cost = posting.cost.as_ref().and_then(|c| c.amount.clone());
+        Some(Self { units, cost })
+    }
+
+    /// The total cost, or just the units if not held at cost
+    pub fn cost(&self) -> Amount {
+        match &self.cost {
+            Some(cost) => Amount::new(self.units.number * cost.number, cost.ccy.clone()),
+            None => self.units.clone(),
+        }
+    }
+
+    /// Whether `other` can be combined into this position
+    /// Costs are compared exactly, as different lots must stay apart
+    fn same_lot(&self, other: &Position) -> bool {
+        let key = |p: &Position| p.cost.as_ref().map(|c| (c.number, c.ccy.clone()));
+        self.units.ccy == other.units.ccy && key(self) == key(other)
+    }
+
+    fn sort_key(&self) -> (&str, Option<(&str, Decimal)>) {
+        let cost = self.cost.as_ref().map(|c| (c.ccy.as_str(), c.number));
+        (&self.units.ccy, cost)
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.cost {
+            Some(cost) => write!(f, "{units} {{{cost}}}", units = self.units),
+            None => write!(f, "{units}", units = self.units),
+        }
+    }
+}
+
+/// The weight of a Posting when balancing: its cost if held at cost,
+/// otherwise converted at its price if it has one, otherwise its units
+pub fn weight(posting: &Posting) -> Option<Amount> {
+    let units = posting.amount.as_ref()?;
+    if let Some(cost) = posting.cost.as_ref().and_then(|c| c.amount.as_ref()) {
+        return Some(Amount::new(units.number * cost.number, cost.ccy.clone()));
+    }
+    match &posting.price {
+        Some(price) => match price.number {
+            Some(number) => Some(Amount::new(units.number * number, price.ccy.clone())),
+            None => Some(units.clone()),
+        },
+        None => Some(units.clone()),
+    }
+}
+
+/// A sum of Positions with no zero positions
+/// Lots are kept in the order they were first added, so that reductions
+/// can be booked against the oldest first, and are sorted by currency then cost for output
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    positions: Vec<Position>,
+}
+
+impl PartialEq for Inventory {
+    fn eq(&self, other: &Self) -> bool {
+        self.positions() == other.positions()
+    }
+}
+
+impl Inventory {
+    /// The positions sorted by currency then cost
+    pub fn positions(&self) -> Vec<&Position> {
+        let mut positions: Vec<&Position> = self.positions.iter().collect();
+        positions.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        positions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn add(&mut self, pos: &Position) {
+        if let Some(i) = self.positions.iter().position(|p| p.same_lot(pos)) {
+            self.positions[i].units.number += pos.units.number;
+            if self.positions[i].units.number.is_zero() {
+                self.positions.remove(i);
+            }
+            return;
+        }
+        let mut remaining = pos.units.number;
+        if pos.cost.is_none() {
+            remaining = self.reduce_lots(&pos.units);
+        }
+        if !remaining.is_zero() {
+            let mut pos = pos.clone();
+            pos.units.number = remaining;
+            self.positions.push(pos);
+        }
+    }
+
+    /// The reductions that `units` with no cost would make to the lots held at cost,
+    /// oldest first, and the units left over
+    pub fn reductions(&self, units: &Amount) -> (Vec<Position>, Decimal) {
+        let mut reductions: Vec<Position> = Vec::new();
+        let mut remaining = units.number;
+        for lot in &self.positions {
+            if remaining.is_zero() {
+                break;
+            }
+            let reduces = lot.cost.is_some()
+                && lot.units.ccy == units.ccy
+                && lot.units.number.is_sign_positive() != remaining.is_sign_positive();
+            if !reduces {
+                continue;
+            }
+            let booked = match lot.units.number.abs() < remaining.abs() {
+                true => -lot.units.number,
+                false => remaining,
+            };
+            let mut reduction = lot.clone();
+            reduction.units.number = booked;
+            reductions.push(reduction);
+            remaining -= booked;
+        }
+        (reductions, remaining)
+    }
+
+    /// Book `units` with no cost against the lots held at cost that it reduces,
+    /// oldest first, returning the units left over
+    fn reduce_lots(&mut self, units: &Amount) -> Decimal {
+        let (reductions, remaining) = self.reductions(units);
+        for reduction in &reductions {
+            self.add(reduction);
+        }
+        remaining
+    }
+
+    pub fn add_amount(&mut self, amount: &Amount) {
+        self.add(&Position::new(amount.clone(), None));
+    }
+
+    pub fn add_inventory(&mut self, other: &Inventory) {
+        for pos in &other.positions {
+            self.add(pos);
+        }
+    }
+
+    /// The same positions without their costs
+    pub fn units(&self) -> Inventory {
+        let mut inv = Inventory::default();
+        for pos in self.positions() {
+            inv.add_amount(&pos.units);
+        }
+        inv
+    }
+
+    /// The total cost of each position, or its units if not held at cost
+    pub fn cost(&self) -> Inventory {
+        let mut inv = Inventory::default();
+        for pos in self.positions() {
+            inv.add_amount(&pos.cost());
+        }
+        inv
+    }
+}
+
+impl fmt::Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let positions: Vec<String> = self.positions().iter().map(|p| p.to_string()).collect();
+        write!(f, "{}", positions.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(number: i64, ccy: &str) -> Amount {
+        Amount::new(Decimal::new(number, 0), ccy.to_string())
+    }
+
+    #[test]
+    fn test_inventory() {
+        let mut inv = Inventory::default();
+        inv.add(&Position::new(amount(10, "GOOG"), Some(amount(50, "GBP"))));
+        inv.add(&Position::new(amount(5, "GOOG"), Some(amount(60, "GBP"))));
+        inv.add(&Position::new(amount(-2, "GOOG"), Some(amount(50, "GBP"))));
+        inv.add_amount(&amount(-100, "GBP"));
+        assert!(inv.positions().len() == 3);
+        assert!(inv.to_string() == "-100 GBP, 8 GOOG {50 GBP}, 5 GOOG {60 GBP}");
+        assert!(inv.units().to_string() == "-100 GBP, 13 GOOG");
+        assert!(inv.cost().to_string() == "600 GBP");
+
+        inv.add_amount(&amount(100, "GBP"));
+        assert!(inv.positions().len() == 2);
+    }
+
+    #[test]
+    fn test_inventory_reduce() {
+        let mut inv = Inventory::default();
+        inv.add(&Position::new(amount(10, "GOOG"), Some(amount(60, "GBP"))));
+        inv.add(&Position::new(amount(10, "GOOG"), Some(amount(50, "GBP"))));
+        // A sale with no cost comes out of the oldest lot first
+        inv.add_amount(&amount(-5, "GOOG"));
+        assert!(inv.to_string() == "10 GOOG {50 GBP}, 5 GOOG {60 GBP}");
+        assert!(inv.cost().to_string() == "800 GBP");
+        inv.add_amount(&amount(-8, "GOOG"));
+        assert!(inv.to_string() == "7 GOOG {50 GBP}");
+        // A sale of more than is held leaves a position with no cost
+        inv.add_amount(&amount(-9, "GOOG"));
+        assert!(inv.to_string() == "-2 GOOG");
+        assert!(inv.units().to_string() == "-2 GOOG");
+    }
+}
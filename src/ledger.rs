@@ -4,6 +4,7 @@ use crate::data::{Directive, Options};
 use crate::error::BeanError;
 
 #[pyclass]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Ledger {
     #[pyo3(get)]
     pub dirs: Vec<Directive>,
@@ -1,18 +1,36 @@
 //! # bean-rs
 //!
 //! `bean-rs` is a [beancount](https://github.com/beancount/beancount) clone (one day...) in Rust
+//!
+//! Pads only become Transactions when `book::get_balances` runs, so the functions
+//! that report on a list of Directives (in `book`, `reports`, `holdings` and `period`)
+//! expect it to have been run first, as `load_balanced` and `load_period` do.
 
 pub mod book;
+pub mod cst;
 pub mod data;
 pub mod error;
+pub mod filter;
+pub mod format;
 mod grammar;
+pub mod holdings;
+pub mod inventory;
 pub mod ledger;
 pub mod loader;
+pub mod number;
+pub mod period;
+pub mod prices;
+pub mod printer;
+pub mod query;
+pub mod realization;
+pub mod reports;
+pub mod shell;
 pub mod utils;
 
+use chrono::NaiveDate;
 use pyo3::prelude::*;
 
-use data::{AccBal, Directive, Transaction};
+use data::{AccBal, Amount, Comments, Directive, Posting, Transaction};
 
 use crate::error::BeanError;
 use crate::ledger::Ledger;
@@ -20,27 +38,56 @@ use crate::ledger::Ledger;
 /// Loads the provided text into a Vec of Directives
 /// containing opens, closes, transactions etc
 pub fn load(text: String) -> Ledger {
-    let entries = loader::load(&text);
-    let ledger = loader::consume(entries);
+    let cst = cst::parse(&text);
+    let ledger = loader::consume(&cst);
+    let mut errs = ledger.errs;
+    errs.extend(loader::validate_accounts(&ledger.dirs, &ledger.opts));
     let mut dirs = ledger.dirs;
     loader::sort(&mut dirs);
-    book::balance_transactions(&mut dirs);
+    errs.extend(book::balance_transactions(&mut dirs));
     utils::debug_directives(&dirs);
     Ledger {
         dirs,
-        errs: ledger.errs,
+        errs,
         opts: ledger.opts,
     }
 }
 
 /// Check and calculate balances for file at path
 pub fn balance(path: &str) -> (AccBal, Vec<BeanError>) {
+    let (ledger, bals) = load_balanced(path);
+    (bals, ledger.errs)
+}
+
+/// Load and check the file at path, keeping the Ledger for further reports
+/// The Ledger includes the Transactions generated from Pads and all errors
+pub fn load_balanced(path: &str) -> (Ledger, AccBal) {
     let text = std::fs::read_to_string(path).expect("cannot read file");
-    let mut ledger = load(text);
+    balance_ledger(load(text))
+}
+
+/// Calculate the balances of a loaded Ledger, adding the Transactions
+/// generated from Pads and any errors to it
+pub fn balance_ledger(mut ledger: Ledger) -> (Ledger, AccBal) {
     let (bals, book_errs) = book::get_balances(&mut ledger.dirs);
-    let mut errs = ledger.errs;
-    errs.extend(book_errs);
-    (bals, errs)
+    ledger.errs.extend(book_errs);
+    (ledger, bals)
+}
+
+/// Load and check the file at path, then clamp it to the period from `begin`
+/// (inclusive) to `end` (exclusive), with earlier balances summarized and
+/// the conversions within it added
+/// The balances are those at the end of the clamped Ledger, and any errors in
+/// the Transactions and Opens added by clamping are added to the Ledger's errors
+pub fn load_period(
+    path: &str,
+    begin: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+) -> (Ledger, AccBal) {
+    let (ledger, _) = load_balanced(path);
+    let ledger = period::clamp(&ledger, begin, end);
+    let bals = book::sum_postings(&ledger.dirs);
+    (ledger, bals)
 }
 
 /// Load the ledger from Python
@@ -56,6 +103,9 @@ fn py_load(path: &str) -> Ledger {
 fn _bean_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(py_load, m)?)?;
     m.add_class::<Transaction>()?;
+    m.add_class::<Posting>()?;
+    m.add_class::<Amount>()?;
+    m.add_class::<Comments>()?;
     m.add_class::<Directive>()?;
     Ok(())
 }
@@ -4,7 +4,8 @@ use log::debug;
 use pest::iterators::Pairs;
 use pest::Parser;
 
-use crate::data::{self, DebugLine};
+use crate::cst::{Cst, Element, Entry, Token, TokenKind};
+use crate::data::{self, Comments, DebugLine};
 use crate::data::{Directive, Options};
 use crate::error::{BeanError, ErrorType};
 use crate::grammar::{BeanParser, Rule};
@@ -23,72 +24,215 @@ pub fn load(data: &str) -> Pairs<'_, Rule> {
     entry.into_inner()
 }
 
-/// Convert the AST Pest Pairs into a Vec of Directives
-pub fn consume(entries: Pairs<'_, Rule>) -> Ledger {
-    let mut errs: Vec<BeanError> = Vec::with_capacity(entries.len());
-    let mut dirs: Vec<Directive> = Vec::new();
+/// Update the heading path with any headings in `tokens`
+/// A heading replaces the one at its depth and drops any deeper ones
+/// A line of only `*` has no title, so like in org-mode it isn't a heading
+fn update_section(section: &mut Vec<String>, tokens: &[Token]) {
+    for (depth, title) in tokens.iter().filter_map(|t| t.heading()) {
+        if title.is_empty() {
+            continue;
+        }
+        section.truncate(depth - 1);
+        // Fill any skipped levels, eg a `***` straight after a `*`
+        section.resize(depth - 1, String::new());
+        section.push(title.to_string());
+    }
+}
+
+/// Where a trailing comment is attached
+enum CommentOwner {
+    Directive,
+    Meta(usize),
+    Posting(usize),
+    PostingMeta(usize, usize),
+}
+
+/// Attach the comments in and around the entry to the Directive made from it
+///
+/// Whole-line comments go to the Posting on the next line, or else follow
+/// the line before them, and comments at the end of a line go to whatever
+/// starts on that line.
+fn attach_comments(entry: &Entry, text: &str, d: &mut Directive) {
+    let comments = d.comments_mut();
+    comments.leading = entry
+        .leading_comments()
+        .iter()
+        .map(|t| Comments::text(t.text))
+        .collect();
+
+    // Every line start that belongs to a posting or metadata
+    let mut owners: Vec<(usize, CommentOwner)> = Vec::new();
+    let mut postings = 0;
+    for node in entry.node.nodes() {
+        match node.kind {
+            Rule::metadata => {
+                let i = owners
+                    .iter()
+                    .filter(|(_, o)| matches!(o, CommentOwner::Meta(_)));
+                owners.push((node.span.start, CommentOwner::Meta(i.count())));
+            }
+            Rule::posting => {
+                let p = postings;
+                postings += 1;
+                owners.push((node.span.start, CommentOwner::Posting(p)));
+                let metas = node.nodes().filter(|n| n.kind == Rule::metadata);
+                for (m, meta) in metas.enumerate() {
+                    owners.push((meta.span.start, CommentOwner::PostingMeta(p, m)));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let tokens = entry.node.leaves().into_iter().filter_map(|e| match e {
+        Element::Token(t) => Some(t),
+        Element::Node(_) => None,
+    });
+    let tokens = tokens.chain(entry.trailing.iter().cloned());
+    for token in tokens.filter(|t| t.kind == TokenKind::Comment) {
+        let start = token.span.start;
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let comment = Comments::text(token.text);
+        if text[line_start..start].trim().is_empty() {
+            // A whole-line comment goes before a posting on the next line,
+            // or else after whatever is on the line before it
+            let next = owners.iter().find(|(s, _)| *s > start);
+            if let Some((_, CommentOwner::Posting(p))) = next {
+                posting_comments(d, *p).leading.push(comment);
+                continue;
+            }
+            let prev = owners.iter().rev().find(|(s, _)| *s < start);
+            match prev.map_or(&CommentOwner::Directive, |(_, o)| o) {
+                CommentOwner::Directive => d.comments_mut().following.push(comment),
+                CommentOwner::Meta(m) => d.meta_mut()[*m].following.push(comment),
+                CommentOwner::Posting(p) => posting_comments(d, *p).following.push(comment),
+                CommentOwner::PostingMeta(p, m) => {
+                    if let Directive::Transaction(tx) = d {
+                        tx.postings[*p].meta[*m].following.push(comment);
+                    }
+                }
+            }
+            continue;
+        }
+        let owner = owners.iter().find(|(s, _)| *s == line_start);
+        match owner.map_or(&CommentOwner::Directive, |(_, o)| o) {
+            CommentOwner::Directive => d.comments_mut().trailing = Some(comment),
+            CommentOwner::Meta(m) => d.meta_mut()[*m].comment = Some(comment),
+            CommentOwner::Posting(p) => posting_comments(d, *p).trailing = Some(comment),
+            CommentOwner::PostingMeta(p, m) => {
+                if let Directive::Transaction(tx) = d {
+                    tx.postings[*p].meta[*m].comment = Some(comment);
+                }
+            }
+        }
+    }
+}
+
+/// The Comments of the `p`th Posting, if `d` is a Transaction
+fn posting_comments(d: &mut Directive, p: usize) -> &mut Comments {
+    match d {
+        Directive::Transaction(tx) => &mut tx.postings[p].comments,
+        _ => d.comments_mut(),
+    }
+}
+
+/// Convert the entries in the Cst into a Vec of Directives
+/// Each Directive records the section (heading path) it was found under
+/// and the comments that belong to it
+pub fn consume(cst: &Cst) -> Ledger {
+    let mut errs: Vec<BeanError> = Vec::new();
+    let mut dirs: Vec<Directive> = Vec::with_capacity(cst.entries.len());
     let mut opts = Options::default();
-    for entry in entries {
-        debug!("{:?}\t{:?}", entry.as_rule(), entry.as_span(),);
-        match entry.as_rule() {
+    let mut section: Vec<String> = Vec::new();
+    for cst_entry in &cst.entries {
+        update_section(&mut section, &cst_entry.leading);
+        let count = dirs.len();
+        let node = &cst_entry.node;
+        debug!("{:?}\t{:?}", node.kind, node.span);
+        match node.kind {
             Rule::option => {
-                opts.update_from_entry(entry);
+                if let Err(err) = opts.update_from_node(node) {
+                    errs.push(err);
+                }
             }
             Rule::custom => {
-                dirs.push(Directive::ConfigCustom(data::ConfigCustom::from_entry(
-                    entry,
-                )));
+                dirs.push(Directive::ConfigCustom(data::ConfigCustom::from_node(node)));
             }
             Rule::query => {
-                dirs.push(Directive::Query(data::Query::from_entry(entry)));
+                dirs.push(Directive::Query(data::Query::from_node(node)));
             }
             Rule::commodity => {
-                dirs.push(Directive::Commodity(data::Commodity::from_entry(entry)));
+                dirs.push(Directive::Commodity(data::Commodity::from_node(node)));
             }
             Rule::open => {
-                dirs.push(Directive::Open(data::Open::from_entry(entry)));
+                dirs.push(Directive::Open(data::Open::from_node(node)));
             }
             Rule::close => {
-                dirs.push(Directive::Close(data::Close::from_entry(entry)));
-            }
-            Rule::balance => {
-                dirs.push(Directive::Balance(data::Balance::from_entry(entry)));
+                dirs.push(Directive::Close(data::Close::from_node(node)));
             }
+            Rule::balance => match data::Balance::from_node(node) {
+                Ok(d) => dirs.push(Directive::Balance(d)),
+                Err(err) => errs.push(err),
+            },
             Rule::pad => {
-                dirs.push(Directive::Pad(data::Pad::from_entry(entry)));
-            }
-            Rule::price => {
-                dirs.push(Directive::Price(data::Price::from_entry(entry)));
+                dirs.push(Directive::Pad(data::Pad::from_node(node)));
             }
+            Rule::price => match data::Price::from_node(node) {
+                Ok(d) => dirs.push(Directive::Price(d)),
+                Err(err) => errs.push(err),
+            },
             Rule::document => {
-                dirs.push(Directive::Document(data::Document::from_entry(entry)));
+                dirs.push(Directive::Document(data::Document::from_node(node)));
             }
             Rule::note => {
-                dirs.push(Directive::Note(data::Note::from_entry(entry)));
-            }
-            Rule::transaction => {
-                dirs.push(Directive::Transaction(data::Transaction::from_entry(entry)));
+                dirs.push(Directive::Note(data::Note::from_node(node)));
             }
+            Rule::transaction => match data::Transaction::from_node(node) {
+                Ok(d) => dirs.push(Directive::Transaction(d)),
+                Err(err) => errs.push(err),
+            },
             Rule::EOI => {
                 debug!("Hit EOI");
             }
             Rule::badline => {
-                let (line, _) = entry.line_col();
-                let debug = DebugLine::new(line);
+                let debug = DebugLine::new(node.line);
                 let err =
                     BeanError::new(ErrorType::Badline, &debug, "Found unparseable line", None);
                 errs.push(err);
             }
             _ => {
-                let (l, _) = entry.line_col();
-                let debug = DebugLine::new(l);
+                let debug = DebugLine::new(node.line);
                 unreachable!("Found unexpected entry in file, abort.\n{debug}");
             }
         };
+        if let Some(d) = dirs.get_mut(count) {
+            d.set_section(section.clone());
+            attach_comments(cst_entry, cst.text, d);
+        }
     }
     Ledger { dirs, errs, opts }
 }
 
+/// Check that every account has one of the roots configured by the `name_*` options
+/// Must be run after all options have been read
+pub fn validate_accounts(dirs: &[Directive], opts: &Options) -> Vec<BeanError> {
+    let mut errs: Vec<BeanError> = Vec::new();
+    for d in dirs {
+        for account in d.accounts() {
+            if opts.account_type(account).is_none() {
+                let err = BeanError::new(
+                    ErrorType::InvalidAccount,
+                    d.debug(),
+                    &format!("Invalid root for account: {account}"),
+                    Some(d),
+                );
+                errs.push(err);
+            }
+        }
+    }
+    errs
+}
+
 /// Sort the Directives by date and `order` inplace
 pub fn sort(dirs: &mut [Directive]) {
     dirs.sort_by(|a, b| match a.date().cmp(b.date()) {
@@ -100,15 +244,18 @@ pub fn sort(dirs: &mut [Directive]) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{cst, printer};
+
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_parse() {
         let text = r#"2023-01-01 open Assets:Bank GBP"#;
-        let entries = load(&text);
+        let cst = cst::parse(text);
         let Ledger {
             dirs,
             errs: _,
             opts: _,
-        } = consume(entries);
+        } = consume(&cst);
         let got = &dirs[0];
         match got {
             Directive::Open(_) => (),
@@ -116,26 +263,182 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_consume_edited() {
+        let text = "2023-01-01 * \"Shop\"\n  Expenses:Food  10.00 GBP\n  Assets:Bank\n";
+        let mut cst = cst::parse(text);
+        let offset = text.find("10.00").unwrap();
+        let number = cst.entries[0].node.node_at_mut(offset).unwrap();
+        assert!(number.kind == Rule::number);
+        number.leaf = "12.50";
+        let Ledger { dirs, .. } = consume(&cst);
+        let Directive::Transaction(tx) = &dirs[0] else {
+            panic!("Expected a Transaction");
+        };
+        let amount = tx.postings[0].amount.as_ref().unwrap();
+        assert!(amount.number == rust_decimal::Decimal::new(1250, 2));
+    }
+
     #[test]
     fn test_bad_consume() {
         let text = r#"
             2023-01-01 foo
         "#;
-        let entries = load(&text);
+        let cst = cst::parse(text);
         let Ledger {
             dirs: _,
             errs,
             opts: _,
-        } = consume(entries);
+        } = consume(&cst);
         assert!(errs.len() == 1);
     }
 
+    #[test]
+    fn test_account_roots() {
+        let text = r#"
+option "name_assets" "Aktiva"
+option "name_expenses" "Aufwand"
+2023-01-01 open Aktiva:Bank
+2023-01-01 open Aufwand:Essen
+2023-01-01 open Assets:Bank
+        "#;
+        let cst = cst::parse(text);
+        let Ledger { dirs, errs, opts } = consume(&cst);
+        assert!(errs.is_empty());
+        assert!(dirs.len() == 3);
+        let errs = validate_accounts(&dirs, &opts);
+        assert!(errs.len() == 1);
+        assert!(errs[0].ty == ErrorType::InvalidAccount);
+    }
+
+    #[test]
+    fn test_bad_option() {
+        let text = r#"
+option "name_assets" "aktiva"
+option "foo" "bar"
+option "booking_method" "FIFO"
+option "render_commas" "TRUE"
+option "account_current_earnings" "Earnings:current"
+option "account_previous_earnings" "Retained:Previous"
+        "#;
+        let cst = cst::parse(text);
+        let Ledger { errs, opts, .. } = consume(&cst);
+        assert!(errs.len() == 3);
+        assert!(errs.iter().all(|e| e.ty == ErrorType::InvalidOption));
+        assert!(opts.name_assets == "Assets");
+        assert!(opts.account_current_earnings() == "Equity:Earnings:Current");
+        assert!(opts.account_previous_earnings() == "Equity:Retained:Previous");
+    }
+
+    #[test]
+    fn test_sections() {
+        let text = r#"2023-01-01 open Assets:Cash
+* Home
+** Rental flat
+2023-01-01 open Assets:Rent
+*** Repairs
+2023-01-01 open Expenses:Repairs
+** Car
+2023-01-01 open Expenses:Car
+*
+2023-01-01 open Expenses:Food
+* Food
+2023-01-01 open Expenses:Snacks
+        "#;
+        let cst = cst::parse(text);
+        let Ledger { dirs, .. } = consume(&cst);
+        let sections: Vec<&[String]> = dirs.iter().map(|d| d.section()).collect();
+        assert!(sections[0].is_empty());
+        assert!(sections[1] == ["Home", "Rental flat"]);
+        assert!(sections[2] == ["Home", "Rental flat", "Repairs"]);
+        assert!(sections[3] == ["Home", "Car"]);
+        assert!(sections[4] == ["Home", "Car"]);
+        assert!(sections[5] == ["Food"]);
+        assert!(dirs[2].in_section("Rental flat"));
+        assert!(!dirs[3].in_section("Rental flat"));
+    }
+
+    #[test]
+    fn test_comments() {
+        let text = r#"
+; about the account
+2023-01-01 open Assets:Bank GBP ; trailing
+  note: "x" ; on the meta
+
+2023-01-02 * "Shop" ; on the header
+  Assets:Bank  -10.00 GBP ; on the posting
+    receipt: "r1" ; on the posting meta
+  ; before the second posting
+  Expenses:Food ; last line
+"#;
+        let cst = cst::parse(text);
+        let Ledger { dirs, errs, .. } = consume(&cst);
+        assert!(errs.is_empty());
+        let Directive::Open(open) = &dirs[0] else {
+            panic!("Expected an Open");
+        };
+        assert!(open.comments.leading == ["about the account"]);
+        assert!(open.comments.trailing.as_deref() == Some("trailing"));
+        assert!(open.meta[0].comment.as_deref() == Some("on the meta"));
+
+        let Directive::Transaction(tx) = &dirs[1] else {
+            panic!("Expected a Transaction");
+        };
+        assert!(tx.comments.leading.is_empty());
+        assert!(tx.comments.trailing.as_deref() == Some("on the header"));
+        let (p1, p2) = (&tx.postings[0], &tx.postings[1]);
+        assert!(p1.comments.trailing.as_deref() == Some("on the posting"));
+        assert!(p1.meta[0].comment.as_deref() == Some("on the posting meta"));
+        assert!(p2.comments.leading == ["before the second posting"]);
+        assert!(p2.comments.trailing.as_deref() == Some("last line"));
+
+        let printed = dirs[1].to_string();
+        assert!(printed.contains("\"Shop\" ; on the header\n"));
+        assert!(printed.contains("  ; before the second posting\n  Expenses:Food ; last line"));
+    }
+
+    #[test]
+    fn test_comments_round_trip() {
+        let text = r#"2023-01-01 open Assets:Bank GBP
+  ; after the header
+  a: "x"
+  ; between the meta
+  b: "y"
+  ; after the meta
+
+2023-01-02 * "Shop"
+  Assets:Bank  -10.00 GBP
+    m: "1"
+    ; in the posting meta
+    n: "2"
+  ; before the second posting
+  Expenses:Food
+  ; after the last posting
+"#;
+        let cst = cst::parse(text);
+        let ledger = consume(&cst);
+        let Directive::Open(open) = &ledger.dirs[0] else {
+            panic!("Expected an Open");
+        };
+        assert!(open.comments.leading.is_empty());
+        assert!(open.comments.following == ["after the header"]);
+        assert!(open.meta[0].following == ["between the meta"]);
+        assert!(open.meta[1].following == ["after the meta"]);
+        let Directive::Transaction(tx) = &ledger.dirs[1] else {
+            panic!("Expected a Transaction");
+        };
+        assert!(tx.comments.leading.is_empty());
+        assert!(tx.postings[0].meta[0].following == ["in the posting meta"]);
+        assert!(tx.postings[1].comments.following == ["after the last posting"]);
+        assert_eq!(printer::print_ledger(&ledger), text);
+    }
+
     #[test]
     fn test_consume() {
         let text = r#"
             option "operating_currency" "GBP"
         "#;
-        let entries = load(&text);
-        consume(entries);
+        let cst = cst::parse(text);
+        consume(&cst);
     }
 }
@@ -1,12 +1,25 @@
 use std::process::ExitCode;
 use std::process::Termination;
 
-use clap::{Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use regex::Regex;
 
 // extern crate bean_rs;
-use bean_rs::balance;
+use bean_rs::data::AccBal;
 use bean_rs::error::BeanError;
+use bean_rs::filter::{self, Filter};
+use bean_rs::format;
+use bean_rs::holdings::{self, Holdings};
+use bean_rs::ledger::Ledger;
+use bean_rs::period;
+use bean_rs::prices::PriceMap;
+use bean_rs::query;
+use bean_rs::realization::TreeOptions;
+use bean_rs::reports;
+use bean_rs::shell;
 use bean_rs::utils;
+use bean_rs::{balance, book, load_balanced, load_period};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -16,14 +29,257 @@ struct Cli {
     command: Commands,
 }
 
+/// How to show the account tree
+#[derive(Args)]
+struct TreeArgs {
+    /// Only show accounts this many levels deep
+    #[arg(short, long)]
+    depth: Option<usize>,
+    /// Hide accounts with a zero balance
+    #[arg(long)]
+    hide_zero: bool,
+}
+
+impl TreeArgs {
+    fn options(&self) -> TreeOptions {
+        TreeOptions {
+            depth: self.depth,
+            hide_zero: self.hide_zero,
+        }
+    }
+}
+
+/// The period and Directives to report on
+#[derive(Args, Clone)]
+struct ScopeArgs {
+    /// Only include transactions on or after this date,
+    /// with earlier ones summarized into opening balances
+    #[arg(short, long)]
+    begin: Option<NaiveDate>,
+    /// Only include transactions before this date
+    #[arg(short, long)]
+    end: Option<NaiveDate>,
+    /// Balances at the end of this date, the same as `--end` the day after
+    #[arg(long, conflicts_with = "end")]
+    as_of: Option<NaiveDate>,
+    /// The period to include instead of the dates, eg 2023, 2023-Q1, 2023-03 or 2023-01..2023-06
+    #[arg(short, long, value_parser = parse_period, conflicts_with_all = ["begin", "end", "as_of"])]
+    period: Option<Period>,
+    /// Book the unrealized gains of holdings at cost at the end,
+    /// into Unrealized subaccounts of each account and its Income account
+    #[arg(long)]
+    unrealized: bool,
+    /// Close the books at the end, moving Income and Expenses into Equity:Earnings:Current
+    #[arg(long)]
+    close: bool,
+    /// Only include Directives that match, eg "account:^Expenses tag:trip date:2025"
+    #[arg(short, long, value_parser = parse_filter, allow_hyphen_values = true)]
+    filter: Option<Filter>,
+}
+
+/// The start (inclusive) and end (exclusive) of a period, either of which may be open
+type Period = (Option<NaiveDate>, Option<NaiveDate>);
+
+fn parse_period(text: &str) -> Result<Period, String> {
+    filter::parse_period(text).map_err(|e| e.msg)
+}
+
+fn parse_filter(text: &str) -> Result<Filter, String> {
+    Filter::parse(text).map_err(|e| e.msg)
+}
+
+impl ScopeArgs {
+    /// The inclusive start date, from `--begin` or `--period`
+    fn begin(&self) -> Option<NaiveDate> {
+        self.period.map_or(self.begin, |(begin, _)| begin)
+    }
+
+    /// The exclusive end date, from `--end`, `--as-of` or `--period`
+    fn end(&self) -> Option<NaiveDate> {
+        match self.period {
+            Some((_, end)) => end,
+            None => self.as_of.and_then(|d| d.succ_opt()).or(self.end),
+        }
+    }
+
+    fn load(&self, path: &str) -> (Ledger, AccBal) {
+        let (mut ledger, mut bals) = load_period(path, self.begin(), self.end());
+        if self.unrealized {
+            let date = match self.end() {
+                Some(end) => end.pred_opt(),
+                None => ledger.dirs.iter().map(|d| *d.date()).max(),
+            };
+            if let Some(date) = date {
+                ledger = holdings::add_unrealized(&ledger, date, Some("Unrealized"));
+                bals = book::sum_postings(&ledger.dirs);
+            }
+        }
+        if self.close {
+            ledger = period::close(&ledger, self.end());
+            bals = book::sum_postings(&ledger.dirs);
+        }
+        if let Some(filter) = &self.filter {
+            ledger.dirs = filter.apply(&ledger.dirs);
+            bals = book::sum_postings(&ledger.dirs);
+        }
+        (ledger, bals)
+    }
+}
+
+/// The format of tabular output
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    Table,
+    Csv,
+    Json,
+}
+
+impl Output {
+    fn print(&self, table: &query::Table) {
+        match self {
+            Output::Table => print!("{}", table.render()),
+            Output::Csv => print!("{}", table.to_csv()),
+            Output::Json => print!("{}", table.to_json()),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Display account balances
-    Balance { path: String },
+    Balance {
+        path: String,
+        /// Only count transactions under the heading with this title
+        #[arg(short, long)]
+        section: Option<String>,
+        /// Show balances separately for each section
+        #[arg(long, conflicts_with = "section")]
+        by_section: bool,
+        /// Show a column for each month, quarter or year
+        #[arg(short, long, conflicts_with_all = ["section", "by_section"])]
+        interval: Option<reports::Interval>,
+        /// Show balances at the end of each period rather than changes during it
+        #[arg(long, requires = "interval")]
+        cumulative: bool,
+        /// How to print the columns for each period
+        #[arg(short, long, value_enum, default_value_t = Output::Table, requires = "interval")]
+        output: Output,
+        #[command(flatten)]
+        scope: ScopeArgs,
+        #[command(flatten)]
+        tree: TreeArgs,
+    },
+    /// Display the balance sheet
+    Bs {
+        path: String,
+        #[command(flatten)]
+        scope: ScopeArgs,
+        #[command(flatten)]
+        tree: TreeArgs,
+    },
+    /// Display the income statement
+    Is {
+        path: String,
+        #[command(flatten)]
+        scope: ScopeArgs,
+        #[command(flatten)]
+        tree: TreeArgs,
+    },
+    /// Display the cash flow statement, the movements of cash by what they were for
+    Cashflow {
+        path: String,
+        /// A regex for cash accounts, including their subaccounts, as well as
+        /// those opened with `cashflow: "cash"` metadata
+        #[arg(short, long)]
+        cash: Option<String>,
+        #[command(flatten)]
+        scope: ScopeArgs,
+        #[command(flatten)]
+        tree: TreeArgs,
+    },
+    /// Display debits and credits for every account, which should sum to zero
+    TrialBalance {
+        path: String,
+        #[command(flatten)]
+        scope: ScopeArgs,
+    },
+    /// Display the postings to matching accounts with a running balance
+    Register {
+        path: String,
+        /// A regex that must match the whole account name
+        account: String,
+        /// Include the subaccounts of matching accounts
+        #[arg(short, long)]
+        subaccounts: bool,
+        /// Show the other postings of each transaction
+        #[arg(short, long)]
+        others: bool,
+        #[command(flatten)]
+        scope: ScopeArgs,
+    },
+    /// Display net worth and the balances of matching accounts over time
+    Series {
+        path: String,
+        /// Regexes for accounts to show, each including its subaccounts
+        accounts: Vec<String>,
+        /// The step between balances
+        #[arg(short, long, default_value = "month")]
+        interval: reports::Interval,
+        /// Convert into the operating currency at the prices on each date
+        #[arg(short, long)]
+        convert: bool,
+        /// Convert into this currency instead
+        #[arg(long)]
+        currency: Option<String>,
+        #[arg(short, long, value_enum, default_value_t = Output::Table)]
+        output: Output,
+        #[command(flatten)]
+        scope: ScopeArgs,
+    },
+    /// Display the units, cost and market value of each commodity held
+    Holdings {
+        path: String,
+        /// A regex for the accounts to include, with their subaccounts,
+        /// instead of all Assets
+        account: Option<String>,
+        /// Combine each commodity's holdings across accounts
+        #[arg(long)]
+        by_commodity: bool,
+        #[arg(short, long, value_enum, default_value_t = Output::Table)]
+        output: Output,
+        #[command(flatten)]
+        scope: ScopeArgs,
+    },
+    /// Run a query in the beancount query language (BQL)
+    Query {
+        path: String,
+        /// The query, eg "SELECT account, sum(position) GROUP BY account"
+        #[arg(required_unless_present = "run", conflicts_with = "run")]
+        query: Option<String>,
+        /// Run the `query` directive with this name instead
+        #[arg(short, long)]
+        run: Option<String>,
+    },
+    /// Run queries interactively
+    Shell { path: String },
     /// Check for errors and quit
     Check { path: String },
+    /// Align amounts and normalise indentation, keeping comments
+    Format {
+        path: String,
+        /// Rewrite the file instead of printing to stdout
+        #[arg(short, long)]
+        in_place: bool,
+        /// Fail if the file isn't already formatted, without changing it
+        #[arg(long)]
+        check: bool,
+        /// The column that currencies are aligned to
+        #[arg(long, default_value_t = format::DEFAULT_CURRENCY_COLUMN)]
+        currency_column: usize,
+    },
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct CliError {}
 
@@ -45,16 +301,240 @@ fn main() -> ExitCode {
     env_logger::init();
     let cli = Cli::parse();
     match &cli.command {
-        Commands::Balance { path } => {
-            let (bals, errs) = balance(path);
+        Commands::Balance {
+            path,
+            section,
+            by_section,
+            interval,
+            cumulative,
+            output,
+            scope,
+            tree,
+        } => {
+            if let Some(interval) = interval {
+                // Start at the beginning of a period, so the opening balances fall before it
+                let scope = ScopeArgs {
+                    begin: scope.begin().map(|b| interval.start(b)),
+                    end: scope.end(),
+                    as_of: None,
+                    period: None,
+                    ..scope.clone()
+                };
+                let (ledger, _) = scope.load(path);
+                utils::print_errors(&ledger.errs);
+                let report = reports::PeriodicReport::new(
+                    &ledger.dirs,
+                    *interval,
+                    scope.begin(),
+                    scope.end(),
+                    *cumulative,
+                    &tree.options(),
+                    &ledger.opts,
+                );
+                output.print(&report.table());
+                return set_exit(&ledger.errs);
+            }
+            let (ledger, bals) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            let tree = tree.options();
+            let opts = &ledger.opts;
+            if let Some(heading) = section {
+                let bals = book::section_balances(&ledger.dirs, heading);
+                utils::print_section_bals(std::slice::from_ref(heading), bals, &tree, opts);
+            } else if *by_section {
+                for (section, bals) in book::balances_by_section(&ledger.dirs) {
+                    utils::print_section_bals(&section, bals, &tree, opts);
+                }
+            } else {
+                utils::print_bals(bals, &tree, opts);
+            }
+            set_exit(&ledger.errs)
+        }
+        Commands::Bs { path, scope, tree } => {
+            let (ledger, bals) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            let bs = reports::BalanceSheet::new(&bals, &ledger.opts);
+            println!("-- Balance Sheet --");
+            print!("{}", bs.render(&tree.options(), &ledger.opts));
+            set_exit(&ledger.errs)
+        }
+        Commands::Is { path, scope, tree } => {
+            let (ledger, bals) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            let is = reports::IncomeStatement::new(&bals, &ledger.opts);
+            println!("-- Income Statement --");
+            print!("{}", is.render(&tree.options(), &ledger.opts));
+            set_exit(&ledger.errs)
+        }
+        Commands::Cashflow {
+            path,
+            cash,
+            scope,
+            tree,
+        } => {
+            let re = match cash.as_deref().map(|c| reports::account_regex(c, true)) {
+                Some(Err(err)) => {
+                    eprintln!("Invalid account regex: {err}");
+                    return ExitCode::FAILURE;
+                }
+                re => re.and_then(Result::ok),
+            };
+            let (ledger, _) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            let cf = reports::CashFlow::new(&ledger.dirs, scope.begin(), re.as_ref(), &ledger.opts);
+            println!("-- Cash Flow --");
+            print!("{}", cf.render(&tree.options(), &ledger.opts));
+            set_exit(&ledger.errs)
+        }
+        Commands::TrialBalance { path, scope } => {
+            let (ledger, bals) = scope.load(path);
+            let tb = reports::TrialBalance::new(&bals, &ledger.opts);
+            let mut errs = ledger.errs;
+            errs.extend(tb.check());
             utils::print_errors(&errs);
-            utils::print_bals(bals);
+            println!("-- Trial Balance --");
+            print!("{}", tb.render(&ledger.opts));
             set_exit(&errs)
         }
+        Commands::Register {
+            path,
+            account,
+            subaccounts,
+            others,
+            scope,
+        } => {
+            let re = match reports::account_regex(account, *subaccounts) {
+                Ok(re) => re,
+                Err(err) => {
+                    eprintln!("Invalid account regex: {err}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let (ledger, _) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            let rows = reports::register(&ledger.dirs, &re);
+            print!("{}", reports::render_register(&rows, *others, &ledger.opts));
+            set_exit(&ledger.errs)
+        }
+        Commands::Series {
+            path,
+            accounts,
+            interval,
+            convert,
+            currency,
+            output,
+            scope,
+        } => {
+            let mut regexes: Vec<(String, Regex)> = Vec::new();
+            for account in accounts {
+                match reports::account_regex(account, true) {
+                    Ok(re) => regexes.push((account.clone(), re)),
+                    Err(err) => {
+                        eprintln!("Invalid account regex: {err}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            let (ledger, _) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            let opts = &ledger.opts;
+            let mut series = reports::TimeSeries::new(
+                &ledger.dirs,
+                *interval,
+                scope.begin(),
+                scope.end(),
+                &regexes,
+                opts,
+            );
+            if let Some(ccy) = currency
+                .as_ref()
+                .or(convert.then_some(&opts.operating_currency))
+            {
+                series.convert(&PriceMap::new(&ledger.dirs), ccy);
+            }
+            output.print(&series.table(opts));
+            set_exit(&ledger.errs)
+        }
+        Commands::Holdings {
+            path,
+            account,
+            by_commodity,
+            output,
+            scope,
+        } => {
+            let re = match account.as_deref().map(|a| reports::account_regex(a, true)) {
+                Some(Err(err)) => {
+                    eprintln!("Invalid account regex: {err}");
+                    return ExitCode::FAILURE;
+                }
+                re => re.and_then(Result::ok),
+            };
+            let (ledger, _) = scope.load(path);
+            utils::print_errors(&ledger.errs);
+            // Priced at the end of the period, or at the last date in the file
+            let date = match scope.end() {
+                Some(end) => end.pred_opt(),
+                None => ledger.dirs.iter().map(|d| *d.date()).max(),
+            };
+            let date = date.unwrap_or(NaiveDate::MAX);
+            let prices = PriceMap::new(&ledger.dirs);
+            let holdings = Holdings::new(&ledger.dirs, re.as_ref(), &prices, date, &ledger.opts);
+            match by_commodity {
+                true => output.print(&holdings.by_commodity().table()),
+                false => output.print(&holdings.table()),
+            }
+            set_exit(&ledger.errs)
+        }
+        Commands::Query { path, query, run } => {
+            let (ledger, _) = load_balanced(path);
+            utils::print_errors(&ledger.errs);
+            let table = match (query, run) {
+                (_, Some(name)) => query::run_named(&ledger, name),
+                (Some(text), None) => query::run(&ledger, text),
+                (None, None) => unreachable!("clap requires a query or --run"),
+            };
+            match table {
+                Ok(table) => {
+                    print!("{}", table.render());
+                    set_exit(&ledger.errs)
+                }
+                Err(err) => {
+                    utils::print_errors(&vec![err]);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Commands::Shell { path } => shell::run(path),
         Commands::Check { path } => {
             let (_, errs) = balance(path);
             utils::print_errors(&errs);
             set_exit(&errs)
         }
+        Commands::Format {
+            path,
+            in_place,
+            check,
+            currency_column,
+        } => {
+            let text = std::fs::read_to_string(path).expect("cannot read file");
+            let opts = format::FormatOptions {
+                currency_column: *currency_column,
+                ..Default::default()
+            };
+            let formatted = format::format(&text, &opts);
+            if *check {
+                if formatted == text {
+                    return ExitCode::SUCCESS;
+                }
+                eprintln!("Would reformat {path}");
+                return ExitCode::FAILURE;
+            }
+            if *in_place {
+                std::fs::write(path, formatted).expect("cannot write file");
+            } else {
+                print!("{formatted}");
+            }
+            ExitCode::SUCCESS
+        }
     }
 }
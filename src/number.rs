@@ -0,0 +1,154 @@
+//! Evaluation of the numbers and arithmetic expressions used in amounts,
+//! such as `-1,234.50` or `(120.00 / 3)`
+
+use rust_decimal::Decimal;
+
+use crate::cst::Node;
+use crate::data::DebugLine;
+use crate::error::{BeanError, ErrorType};
+use crate::grammar::Rule;
+
+/// Parse a single number literal, following beancount's format:
+/// digits with optional comma separators between groups of three
+/// (after a leading group of one to three), then an optional fractional part
+pub fn parse_number(text: &str) -> Option<Decimal> {
+    let (int, frac) = match text.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (text, None),
+    };
+    let digits = |g: &str| g.chars().all(|c| c.is_ascii_digit());
+    let mut groups = int.split(',');
+    let first = groups.next().unwrap_or("");
+    let first_ok = !first.is_empty() && (!int.contains(',') || first.len() <= 3);
+    let int_ok = first_ok && digits(first) && groups.all(|g| g.len() == 3 && digits(g));
+    let frac_ok = frac.unwrap_or("").chars().all(|c| c.is_ascii_digit());
+    if !int_ok || !frac_ok {
+        return None;
+    }
+    text.replace(',', "").parse().ok()
+}
+
+/// Apply a binary operator, checking for overflow and division by zero
+fn apply(lhs: Decimal, op: Rule, rhs: Decimal) -> Result<Decimal, String> {
+    let (result, symbol) = match op {
+        Rule::add => (lhs.checked_add(rhs), "+"),
+        Rule::sub => (lhs.checked_sub(rhs), "-"),
+        Rule::mul => (lhs.checked_mul(rhs), "*"),
+        _ if rhs.is_zero() => return Err("Division by zero".to_string()),
+        _ => (lhs.checked_div(rhs), "/"),
+    };
+    result.ok_or_else(|| format!("Overflow evaluating {lhs} {symbol} {rhs}"))
+}
+
+/// Evaluate the operands and operators in a `num_expr` Node, with prefix signs binding
+/// tightest, then `*` and `/`, then `+` and `-`, each from left to right
+fn eval_expr(node: &Node) -> Result<Decimal, String> {
+    let mut operands: Vec<Decimal> = Vec::new();
+    let mut ops: Vec<Rule> = Vec::new();
+    let mut negate = false;
+    for n in node.nodes() {
+        match n.kind {
+            Rule::neg => negate = !negate,
+            Rule::pos => (),
+            Rule::add | Rule::sub | Rule::mul | Rule::div => ops.push(n.kind),
+            Rule::number => {
+                let text = n.text();
+                let number =
+                    parse_number(&text).ok_or_else(|| format!("Invalid number: {text}"))?;
+                operands.push(if negate { -number } else { number });
+                negate = false;
+            }
+            Rule::num_expr => {
+                let number = eval_expr(n)?;
+                operands.push(if negate { -number } else { number });
+                negate = false;
+            }
+            rule => unreachable!("Unexpected {rule:?} in number expression"),
+        }
+    }
+    // Multiply and divide into terms, then add and subtract those
+    let mut operands = operands.into_iter();
+    let mut terms: Vec<Decimal> = operands.next().into_iter().collect();
+    let mut term_ops: Vec<Rule> = Vec::new();
+    for (op, rhs) in ops.into_iter().zip(operands) {
+        match op {
+            Rule::mul | Rule::div => {
+                let lhs = terms.pop().unwrap_or_default();
+                terms.push(apply(lhs, op, rhs)?);
+            }
+            _ => {
+                term_ops.push(op);
+                terms.push(rhs);
+            }
+        }
+    }
+    let mut terms = terms.into_iter();
+    let first = terms.next().unwrap_or_default();
+    term_ops
+        .into_iter()
+        .zip(terms)
+        .try_fold(first, |lhs, (op, rhs)| apply(lhs, op, rhs))
+}
+
+/// Evaluate a `num_expr` Node to a Decimal
+pub fn eval(node: &Node) -> Result<Decimal, BeanError> {
+    eval_expr(node).map_err(|msg| {
+        let debug = DebugLine::new(node.line);
+        let msg = format!("{msg} in amount: {text}", text = node.text());
+        BeanError::new(ErrorType::InvalidNumber, &debug, &msg, None)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pest::Parser;
+
+    use super::*;
+    use crate::grammar::BeanParser;
+
+    fn eval_str(text: &str) -> Result<Decimal, BeanError> {
+        let pair = BeanParser::parse(Rule::num_expr, text)
+            .unwrap()
+            .next()
+            .unwrap();
+        eval(&Node::from_pair(pair, text))
+    }
+
+    #[test]
+    fn test_number() {
+        assert!(parse_number("1,234.50") == Some(Decimal::new(123450, 2)));
+        assert!(parse_number("12.") == Some(Decimal::new(12, 0)));
+        assert!(parse_number("1,.0.0").is_none());
+        assert!(parse_number("1..0").is_none());
+        assert!(parse_number("1,,000").is_none());
+        assert!(parse_number("1,2,3").is_none());
+        assert!(parse_number("1234,567").is_none());
+        assert!(parse_number("123,456,789") == Some(Decimal::new(123456789, 0)));
+        assert!(parse_number("1,2345").is_none());
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert!(eval_str("(120.00 / 3)").unwrap() == Decimal::new(40, 0));
+        assert!(eval_str("-1,234.50 + 12").unwrap() == Decimal::new(-122250, 2));
+        assert!(eval_str("2 + 3 * 4").unwrap() == Decimal::new(14, 0));
+        assert!(eval_str("(2 + 3) * 4").unwrap() == Decimal::new(20, 0));
+        assert!(eval_str("10 - 2 - 3").unwrap() == Decimal::new(5, 0));
+        assert!(eval_str("-(1 + 2)").unwrap() == Decimal::new(-3, 0));
+        assert!(eval_str("2 * -3").unwrap() == Decimal::new(-6, 0));
+        assert!(eval_str("1 - 6 / 2 * 3 + 4").unwrap() == Decimal::new(-4, 0));
+        assert!(eval_str("--2").unwrap() == Decimal::new(2, 0));
+    }
+
+    #[test]
+    fn test_bad_expressions() {
+        let err = eval_str("1 / (2 - 2)").unwrap_err();
+        assert!(err.ty == ErrorType::InvalidNumber);
+        let err = eval_str("1,.0.0").unwrap_err();
+        assert!(err.ty == ErrorType::InvalidNumber);
+        for text in ["1,2,3", "1,,000"] {
+            let err = eval_str(text).unwrap_err();
+            assert!(err.msg == format!("Invalid number: {text} in amount: {text}"));
+        }
+    }
+}
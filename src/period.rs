@@ -0,0 +1,498 @@
+//! Limit a Ledger to a period of time, like beancount's `clamp`
+//!
+//! Everything before the start of the period is summarized into one
+//! opening balance Transaction per account, dated the day before it starts,
+//! with a posting for each lot so that positions held at cost keep their `{cost}`.
+//! Income and Expenses from before the period are moved into
+//! `Options::account_previous_earnings`, so that in the clamped Ledger
+//! they only show activity within the period.
+//!
+//! The balances left by conversions between currencies within the period are
+//! moved into `Options::account_current_conversions` at the end of it.
+//!
+//! Closing the books at the end of the period, like beancount's `close` and `clear`,
+//! moves the Income and Expenses within it into `Options::account_current_earnings`.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::book;
+use crate::data::{
+    Account, CcyBal, Cost, Directive, Open, Options, Posting, Transaction, UnitPrice,
+};
+use crate::error::BeanError;
+use crate::inventory::{Inventory, Position};
+use crate::ledger::Ledger;
+use crate::loader;
+
+/// The Ledger limited to Directives from `begin` (inclusive) to `end` (exclusive),
+/// with the balances from before `begin` summarized and any `conversions` needed
+/// at the end of the period
+/// The Ledger should already be balanced; the errors added are those
+/// in the Transactions and Opens that summarize the earlier balances
+pub fn clamp(ledger: &Ledger, begin: Option<NaiveDate>, end: Option<NaiveDate>) -> Ledger {
+    let mut dirs: Vec<Directive> = Vec::new();
+    let mut prior: Vec<Directive> = Vec::new();
+    for d in &ledger.dirs {
+        if end.is_some_and(|end| *d.date() >= end) {
+            continue;
+        }
+        if begin.is_none_or(|begin| *d.date() >= begin) {
+            dirs.push(d.clone());
+            continue;
+        }
+        prior.push(d.clone());
+        // Keep what is still needed in the period, but not the history
+        match d {
+            Directive::Open(_)
+            | Directive::Close(_)
+            | Directive::Commodity(_)
+            | Directive::Price(_)
+            | Directive::ConfigCustom(_) => dirs.push(d.clone()),
+            _ => (),
+        }
+    }
+    let mut errs = ledger.errs.clone();
+    if let Some(begin) = begin {
+        let summary = summarize(&inventories(&prior), begin, ledger);
+        errs.extend(check_summary(&dirs, &summary));
+        dirs.extend(summary);
+    }
+    if let Some(date) = closing_date(&dirs, end) {
+        if let Some(tx) = conversions(&dirs, date, &ledger.opts) {
+            let txs = vec![Directive::Transaction(tx)];
+            let account = ledger.opts.account_current_conversions();
+            dirs.extend(open_missing(&[account], date, &txs, ledger));
+            dirs.extend(txs);
+        }
+    }
+    loader::sort(&mut dirs);
+    Ledger {
+        dirs,
+        errs,
+        opts: ledger.opts.clone(),
+    }
+}
+
+/// Errors in the `summary` Directives, such as an opening balance for a closed account,
+/// checked against the Opens and Closes in `dirs`
+fn check_summary(dirs: &[Directive], summary: &[Directive]) -> Vec<BeanError> {
+    let mut check: Vec<Directive> = dirs
+        .iter()
+        .filter(|d| matches!(d, Directive::Open(_) | Directive::Close(_)))
+        .chain(summary)
+        .cloned()
+        .collect();
+    loader::sort(&mut check);
+    let (_, errs) = book::get_balances(&mut check);
+    errs
+}
+
+/// The lots held in each account at the end of `dirs`
+fn inventories(dirs: &[Directive]) -> BTreeMap<Account, Inventory> {
+    let mut invs: BTreeMap<Account, Inventory> = BTreeMap::new();
+    for d in dirs {
+        if let Directive::Transaction(tx) = d {
+            for p in &tx.postings {
+                if let Some(pos) = Position::from_posting(p) {
+                    invs.entry(p.account.clone()).or_default().add(&pos);
+                }
+            }
+        }
+    }
+    invs
+}
+
+/// Opening balance Transactions (and Opens for the Equity accounts they use)
+/// that bring each account to the lots in `invs`, the day before `begin`
+fn summarize(
+    invs: &BTreeMap<Account, Inventory>,
+    begin: NaiveDate,
+    ledger: &Ledger,
+) -> Vec<Directive> {
+    let opts: &Options = &ledger.opts;
+    let date = begin.pred_opt().unwrap_or(begin);
+    let opening = opts.account_previous_balances();
+    let earnings = opts.account_previous_earnings();
+
+    let mut txs: Vec<Directive> = Vec::new();
+    let mut summary = |account: &str, inv: &Inventory| {
+        let mut positions = inv.positions();
+        positions.sort_by(|a, b| {
+            opts.ccy_order(&a.units.ccy)
+                .cmp(&opts.ccy_order(&b.units.ccy))
+        });
+        let mut postings: Vec<Posting> = Vec::new();
+        for pos in positions {
+            let mut posting =
+                Posting::new(account.to_string(), pos.units.number, pos.units.ccy.clone());
+            posting.cost = pos.cost.clone().map(|amount| Cost {
+                amount: Some(amount),
+            });
+            let weight = pos.cost();
+            postings.push(posting);
+            postings.push(Posting::new(opening.clone(), -weight.number, weight.ccy));
+        }
+        if !postings.is_empty() {
+            let narration = format!("Opening balance for '{account}' (Summarization)");
+            let tx = Transaction::new(date, "S", narration, postings);
+            txs.push(Directive::Transaction(tx));
+        }
+    };
+    // Income and Expenses are combined into one amount per currency
+    let mut accounts: Vec<&Account> = invs.keys().collect();
+    accounts.sort_by(|a, b| opts.account_order(a).cmp(&opts.account_order(b)));
+    let mut previous_earnings = Inventory::default();
+    for account in accounts {
+        if opts
+            .account_type(account)
+            .is_some_and(|ty| ty.is_income_statement())
+        {
+            previous_earnings.add_inventory(&invs[account].units());
+            continue;
+        }
+        summary(account, &invs[account]);
+    }
+    summary(&earnings, &previous_earnings);
+
+    let mut dirs = open_missing(&[opening, earnings], date, &txs, ledger);
+    dirs.extend(txs);
+    dirs
+}
+
+/// Opens for the `accounts` that `txs` use but that aren't opened in the file
+pub fn open_missing(
+    accounts: &[String],
+    date: NaiveDate,
+    txs: &[Directive],
+    ledger: &Ledger,
+) -> Vec<Directive> {
+    let mut opens: Vec<Directive> = Vec::new();
+    for account in accounts {
+        let used = txs.iter().any(|d| d.accounts().contains(&account));
+        let opened = ledger
+            .dirs
+            .iter()
+            .any(|d| matches!(d, Directive::Open(o) if &o.account == account));
+        if used && !opened {
+            opens.push(Directive::Open(Open::new(date, account.clone())));
+        }
+    }
+    opens
+}
+
+/// The date that closing Transactions go on: the day before `end`,
+/// or the date of the last Transaction in `dirs` if there is no end
+fn closing_date(dirs: &[Directive], end: Option<NaiveDate>) -> Option<NaiveDate> {
+    match end {
+        Some(end) => end.pred_opt(),
+        None => dirs
+            .iter()
+            .filter(|d| matches!(d, Directive::Transaction(_)))
+            .map(|d| *d.date())
+            .max(),
+    }
+}
+
+/// A Transaction on `date` to `Options::account_current_conversions` that brings
+/// the sum of every posting in each currency back to zero, like beancount's `conversions`
+/// Postings with a price or cost leave a balance in each currency they convert between,
+/// so without it the Equity on a balance sheet doesn't match the Assets and Liabilities
+/// in each currency. Its postings are priced at zero in `Options::conversion_currency`.
+pub fn conversions(dirs: &[Directive], date: NaiveDate, opts: &Options) -> Option<Transaction> {
+    let mut total = CcyBal::new();
+    for bal in book::sum_postings(dirs).values() {
+        for (ccy, number) in bal {
+            *total.entry(ccy.clone()).or_default() += number;
+        }
+    }
+    let account = opts.account_current_conversions();
+    let mut postings: Vec<Posting> = Vec::new();
+    let mut amounts: Vec<String> = Vec::new();
+    for (ccy, number) in opts.sorted_ccys(&total) {
+        if number.is_zero() {
+            continue;
+        }
+        let mut posting = Posting::new(account.clone(), -number, ccy.clone());
+        posting.price = Some(UnitPrice {
+            number: Some(Decimal::ZERO),
+            ccy: opts.conversion_currency.clone(),
+        });
+        postings.push(posting);
+        amounts.push(format!("{number} {ccy}"));
+    }
+    if postings.is_empty() {
+        return None;
+    }
+    let narration = format!("Conversion for ({})", amounts.join(", "));
+    Some(Transaction::new(date, "C", narration, postings))
+}
+
+/// The Ledger up to `end` (exclusive), with the balance of each Income and Expenses
+/// account moved into `Options::account_current_earnings` by a Transaction dated the
+/// day before `end`, or on the last date in the Ledger if there is no end,
+/// and any `conversions` needed
+pub fn close(ledger: &Ledger, end: Option<NaiveDate>) -> Ledger {
+    let mut dirs: Vec<Directive> = ledger
+        .dirs
+        .iter()
+        .filter(|d| end.is_none_or(|end| *d.date() < end))
+        .cloned()
+        .collect();
+    if let Some(date) = closing_date(&dirs, end) {
+        let opts = &ledger.opts;
+        let earnings = opts.account_current_earnings();
+        let conversions_account = opts.account_current_conversions();
+        let bals = book::sum_postings(&dirs);
+        let mut txs: Vec<Directive> = Vec::new();
+        for account in opts.sorted_accounts(&bals) {
+            if !opts
+                .account_type(account)
+                .is_some_and(|ty| ty.is_income_statement())
+            {
+                continue;
+            }
+            let mut postings: Vec<Posting> = Vec::new();
+            for (ccy, number) in opts.sorted_ccys(&bals[account]) {
+                if !number.is_zero() {
+                    postings.push(Posting::new(account.clone(), -number, ccy.clone()));
+                    postings.push(Posting::new(earnings.clone(), *number, ccy.clone()));
+                }
+            }
+            if !postings.is_empty() {
+                let narration = format!("Transfer balance for '{account}' (Transfer balance)");
+                let tx = Transaction::new(date, "T", narration, postings);
+                txs.push(Directive::Transaction(tx));
+            }
+        }
+        if let Some(tx) = conversions(&dirs, date, opts) {
+            txs.push(Directive::Transaction(tx));
+        }
+        dirs.extend(open_missing(
+            &[earnings, conversions_account],
+            date,
+            &txs,
+            ledger,
+        ));
+        dirs.extend(txs);
+        loader::sort(&mut dirs);
+    }
+    Ledger {
+        dirs,
+        errs: ledger.errs.clone(),
+        opts: ledger.opts.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Amount;
+    use crate::holdings::Holdings;
+
+    const TEXT: &str = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Income:Job
+2023-01-01 open Expenses:Food
+
+2023-01-15 * "Salary"
+  Assets:Bank     1000 GBP
+  Income:Job
+
+2023-01-20 * "Shop"
+  Expenses:Food     20 GBP
+  Assets:Bank
+
+2023-01-25 balance Assets:Bank  980 GBP
+
+2023-02-15 * "Shop"
+  Expenses:Food     30 GBP
+  Assets:Bank
+
+2023-03-15 * "Shop"
+  Expenses:Food     40 GBP
+  Assets:Bank
+"#;
+
+    fn gbp(n: i64) -> CcyBal {
+        CcyBal::from([("GBP".to_string(), Decimal::new(n, 0))])
+    }
+
+    #[test]
+    fn test_clamp() {
+        let ledger = crate::load(TEXT.to_string());
+        let begin = NaiveDate::from_ymd_opt(2023, 2, 1);
+        let end = NaiveDate::from_ymd_opt(2023, 3, 1);
+        let mut clamped = clamp(&ledger, begin, end);
+        assert!(clamped.dirs.iter().all(|d| *d.date() < end.unwrap()));
+        assert!(!clamped
+            .dirs
+            .iter()
+            .any(|d| matches!(d, Directive::Balance(_))));
+
+        let (bals, errs) = book::get_balances(&mut clamped.dirs);
+        assert!(errs.is_empty());
+        assert!(bals["Assets:Bank"] == gbp(950));
+        assert!(bals["Expenses:Food"] == gbp(30));
+        assert!(!bals.contains_key("Income:Job"));
+        assert!(bals["Equity:Earnings:Previous"] == gbp(-980));
+        assert!(bals["Equity:Opening-Balances"] == gbp(0));
+
+        let summaries: Vec<&Transaction> = clamped
+            .dirs
+            .iter()
+            .filter_map(|d| match d {
+                Directive::Transaction(tx) if tx.ty == "S" => Some(tx),
+                _ => None,
+            })
+            .collect();
+        assert!(summaries.len() == 2);
+        assert!(summaries[0].date == NaiveDate::from_ymd_opt(2023, 1, 31).unwrap());
+    }
+
+    #[test]
+    fn test_close() {
+        let mut ledger = crate::load(TEXT.to_string());
+        ledger.opts.account_current_earnings = "Retained".to_string();
+        let begin = NaiveDate::from_ymd_opt(2023, 2, 1);
+        let end = NaiveDate::from_ymd_opt(2023, 3, 1);
+        let mut closed = close(&clamp(&ledger, begin, end), end);
+        let (bals, errs) = book::get_balances(&mut closed.dirs);
+        assert!(errs.is_empty());
+        assert!(bals["Assets:Bank"] == gbp(950));
+        assert!(bals["Expenses:Food"] == gbp(0));
+        assert!(bals["Equity:Retained"] == gbp(30));
+        assert!(bals["Equity:Earnings:Previous"] == gbp(-980));
+
+        let transfers: Vec<&Transaction> = closed
+            .dirs
+            .iter()
+            .filter_map(|d| match d {
+                Directive::Transaction(tx) if tx.ty == "T" => Some(tx),
+                _ => None,
+            })
+            .collect();
+        assert!(transfers.len() == 1);
+        assert!(transfers[0].date == NaiveDate::from_ymd_opt(2023, 2, 28).unwrap());
+
+        // Without an end, everything is closed on the last date
+        let mut closed = close(&ledger, None);
+        let (bals, _) = book::get_balances(&mut closed.dirs);
+        assert!(bals["Income:Job"] == gbp(0));
+        assert!(bals["Equity:Retained"] == gbp(-910));
+    }
+
+    #[test]
+    fn test_conversions() {
+        let text = r#"
+option "conversion_currency" "GBP"
+option "account_current_conversions" "Conversions"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Dollars
+2023-01-01 open Expenses:Food
+
+2023-01-15 * "Exchange"
+  Assets:Dollars   100 USD @ 0.80 GBP
+  Assets:Bank     -80.00 GBP
+
+2023-02-15 * "Shop"
+  Expenses:Food     10 USD
+  Assets:Dollars
+"#;
+        let ledger = crate::load(text.to_string());
+        let date = NaiveDate::from_ymd_opt(2023, 2, 28).unwrap();
+        let tx = conversions(&ledger.dirs, date, &ledger.opts).unwrap();
+        assert!(tx.narration == "Conversion for (-80.00 GBP, 100 USD)");
+        assert!(tx.postings[0].account == "Equity:Conversions");
+        assert!(tx
+            .postings
+            .iter()
+            .all(|p| p.price.as_ref().unwrap().ccy == "GBP"));
+
+        let mut converted = clamp(&ledger, None, None);
+        let (bals, errs) = book::get_balances(&mut converted.dirs);
+        assert!(errs.is_empty());
+        assert!(
+            bals["Equity:Conversions"]
+                == CcyBal::from([
+                    ("GBP".to_string(), Decimal::new(8000, 2)),
+                    ("USD".to_string(), Decimal::new(-100, 0)),
+                ])
+        );
+        assert!(conversions(&converted.dirs, date, &ledger.opts).is_none());
+
+        // Clamping to a period and closing the books convert within the period
+        let begin = NaiveDate::from_ymd_opt(2023, 2, 1);
+        let clamped = clamp(&ledger, begin, None);
+        assert!(conversions(&clamped.dirs, date, &ledger.opts).is_none());
+        let closed = close(&ledger, None);
+        assert!(conversions(&closed.dirs, date, &ledger.opts).is_none());
+        assert!(conversions(&crate::load(TEXT.to_string()).dirs, date, &ledger.opts).is_none());
+    }
+
+    #[test]
+    fn test_clamp_nothing() {
+        let ledger = crate::load(TEXT.to_string());
+        assert!(clamp(&ledger, None, None) == ledger);
+    }
+
+    #[test]
+    fn test_clamp_cost() {
+        let text = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Invest
+2023-01-01 open Income:Gains
+
+2023-01-10 * "Buy"
+  Assets:Invest     10 GOOG {50 GBP}
+  Assets:Bank     -500 GBP
+
+2023-01-20 * "Buy"
+  Assets:Invest     10 GOOG {60 GBP}
+  Assets:Bank     -600 GBP
+
+2023-02-01 * "Sell"
+  Assets:Invest     -5 GOOG {} @ 70 GBP
+  Assets:Bank      350 GBP
+  Income:Gains    -100 GBP
+"#;
+        let ledger = crate::load(text.to_string());
+        let begin = NaiveDate::from_ymd_opt(2023, 3, 1);
+        let mut clamped = clamp(&ledger, begin, None);
+        let summary = clamped
+            .dirs
+            .iter()
+            .find_map(|d| match d {
+                Directive::Transaction(tx) if tx.narration.contains("Assets:Invest") => Some(tx),
+                _ => None,
+            })
+            .unwrap();
+        let lots: Vec<String> = summary
+            .postings
+            .iter()
+            .filter_map(|p| Some(Position::from_posting(p)?.to_string()))
+            .collect();
+        assert!(
+            lots == [
+                "5 GOOG {50 GBP}",
+                "-250 GBP",
+                "10 GOOG {60 GBP}",
+                "-600 GBP"
+            ]
+        );
+
+        let (bals, errs) = book::get_balances(&mut clamped.dirs);
+        assert!(errs.is_empty());
+        assert!(bals["Assets:Invest"]["GOOG"] == Decimal::new(15, 0));
+        assert!(bals["Equity:Opening-Balances"] == gbp(0));
+        assert!(bals["Equity:Earnings:Previous"] == gbp(-100));
+
+        let prices = crate::prices::PriceMap::new(&clamped.dirs);
+        let date = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
+        let holdings = Holdings::new(&clamped.dirs, None, &prices, date, &clamped.opts);
+        let invest = &holdings.rows[1];
+        assert!(invest.book_value == Some(Amount::new(Decimal::new(850, 0), "GBP".into())));
+    }
+}
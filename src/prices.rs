@@ -0,0 +1,113 @@
+//! Prices from `price` Directives, for converting amounts between currencies
+//!
+//! Like beancount's price map, a rate is the latest one on or before the date,
+//! and a missing rate is derived from the inverse if that is known.
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::data::{Amount, CcyBal, Directive};
+
+/// The rates for each (commodity, currency) pair by date
+#[derive(Clone, Debug, Default)]
+pub struct PriceMap {
+    rates: BTreeMap<(String, String), BTreeMap<NaiveDate, Decimal>>,
+}
+
+impl PriceMap {
+    /// Collect the rates from every `price` Directive
+    /// A later Directive for the same pair and date replaces an earlier one
+    pub fn new(dirs: &[Directive]) -> Self {
+        let mut map = Self::default();
+        for d in dirs {
+            if let Directive::Price(p) = d {
+                map.insert(p.date, &p.commodity, &p.amount);
+            }
+        }
+        map
+    }
+
+    /// Record that one unit of `commodity` was worth `amount` on `date`
+    pub fn insert(&mut self, date: NaiveDate, commodity: &str, amount: &Amount) {
+        self.rates
+            .entry((commodity.to_string(), amount.ccy.clone()))
+            .or_default()
+            .insert(date, amount.number);
+    }
+
+    fn latest(&self, base: &str, quote: &str, date: NaiveDate) -> Option<(NaiveDate, Decimal)> {
+        let rates = self.rates.get(&(base.to_string(), quote.to_string()))?;
+        rates.range(..=date).next_back().map(|(d, r)| (*d, *r))
+    }
+
+    /// The value of one unit of `base` in `quote` on `date`, from the more recent
+    /// of the direct rate and the inverse of the reverse rate
+    pub fn rate(&self, base: &str, quote: &str, date: NaiveDate) -> Option<Decimal> {
+        if base == quote {
+            return Some(Decimal::ONE);
+        }
+        let direct = self.latest(base, quote, date);
+        let inverse = self
+            .latest(quote, base, date)
+            .filter(|(_, r)| !r.is_zero())
+            .map(|(d, r)| (d, (Decimal::ONE / r).normalize()));
+        match (direct, inverse) {
+            (Some(direct), Some(inverse)) if inverse.0 > direct.0 => Some(inverse.1),
+            (Some(direct), _) => Some(direct.1),
+            (None, inverse) => inverse.map(|(_, r)| r),
+        }
+    }
+
+    /// The amount in `ccy` on `date`, if there is a rate
+    pub fn convert(&self, amount: &Amount, ccy: &str, date: NaiveDate) -> Option<Amount> {
+        let rate = self.rate(&amount.ccy, ccy, date)?;
+        Some(Amount::new(amount.number * rate, ccy.to_string()))
+    }
+
+    /// The balances converted into `ccy` where there is a rate on `date`,
+    /// keeping the others in their own currency
+    pub fn convert_bal(&self, bal: &CcyBal, ccy: &str, date: NaiveDate) -> CcyBal {
+        let mut converted = CcyBal::new();
+        for (from, number) in bal {
+            let amount = Amount::new(*number, from.clone());
+            let amount = self.convert(&amount, ccy, date).unwrap_or(amount);
+            *converted.entry(amount.ccy).or_default() += amount.number;
+        }
+        converted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prices() {
+        let text = r#"
+2023-01-01 price GOOG  50 GBP
+2023-02-01 price GOOG  60 GBP
+2023-01-01 price USD   0.80 GBP
+2023-03-01 price GBP   1.00 USD
+"#;
+        let ledger = crate::load(text.to_string());
+        let prices = PriceMap::new(&ledger.dirs);
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert!(prices.rate("GOOG", "GBP", d(2022, 12, 31)).is_none());
+        assert!(prices.rate("GOOG", "GBP", d(2023, 1, 31)) == Some(Decimal::new(50, 0)));
+        assert!(prices.rate("GOOG", "GBP", d(2023, 2, 1)) == Some(Decimal::new(60, 0)));
+        assert!(prices.rate("GBP", "USD", d(2023, 2, 1)) == Some(Decimal::new(125, 2)));
+        assert!(prices.rate("USD", "GBP", d(2023, 3, 1)) == Some(Decimal::ONE));
+        assert!(prices.rate("GOOG", "USD", d(2023, 3, 1)).is_none());
+
+        let bal = CcyBal::from([
+            ("GOOG".to_string(), Decimal::new(2, 0)),
+            ("GBP".to_string(), Decimal::new(10, 0)),
+            ("EUR".to_string(), Decimal::new(5, 0)),
+        ]);
+        let converted = prices.convert_bal(&bal, "GBP", d(2023, 2, 1));
+        assert!(converted["GBP"] == Decimal::new(130, 0));
+        assert!(converted["EUR"] == Decimal::new(5, 0));
+    }
+}
@@ -0,0 +1,150 @@
+//! Print a Ledger back out as valid beancount
+//!
+//! Each Directive's `Display` impl produces its own text,
+//! this module takes care of the options and overall layout.
+
+use crate::data::{Directive, Options};
+use crate::ledger::Ledger;
+use crate::utils;
+
+/// An `option` line for each option that differs from the default
+pub fn print_options(opts: &Options) -> String {
+    let default = Options::default();
+    let pairs = [
+        ("title", &opts.title, &default.title),
+        (
+            "operating_currency",
+            &opts.operating_currency,
+            &default.operating_currency,
+        ),
+        ("name_assets", &opts.name_assets, &default.name_assets),
+        (
+            "name_liabilities",
+            &opts.name_liabilities,
+            &default.name_liabilities,
+        ),
+        ("name_equity", &opts.name_equity, &default.name_equity),
+        ("name_income", &opts.name_income, &default.name_income),
+        ("name_expenses", &opts.name_expenses, &default.name_expenses),
+        (
+            "account_previous_balances",
+            &opts.account_previous_balances,
+            &default.account_previous_balances,
+        ),
+        (
+            "account_previous_earnings",
+            &opts.account_previous_earnings,
+            &default.account_previous_earnings,
+        ),
+        (
+            "account_current_earnings",
+            &opts.account_current_earnings,
+            &default.account_current_earnings,
+        ),
+        (
+            "account_current_conversions",
+            &opts.account_current_conversions,
+            &default.account_current_conversions,
+        ),
+        (
+            "conversion_currency",
+            &opts.conversion_currency,
+            &default.conversion_currency,
+        ),
+    ];
+    let mut out = String::new();
+    for (key, val, default) in pairs {
+        if val != default {
+            let line = format!("option {} {}\n", utils::escape(key), utils::escape(val));
+            out.push_str(&line);
+        }
+    }
+    out
+}
+
+/// The heading lines needed to move from section `prev` to section `next`
+/// Headings shared with `prev` are not repeated, but at least the last
+/// heading is written when moving up, so that deeper ones are closed
+/// There is no heading that leaves every section, so nothing is written for that
+pub fn print_headings(prev: &[String], next: &[String]) -> String {
+    if prev == next {
+        return String::new();
+    }
+    let shared = prev.iter().zip(next).take_while(|(a, b)| a == b).count();
+    let start = shared.min(next.len().saturating_sub(1));
+    let mut out = String::new();
+    for (i, title) in next.iter().enumerate().skip(start) {
+        out.push_str(&format!("{stars} {title}\n", stars = "*".repeat(i + 1)));
+    }
+    out
+}
+
+/// The whole Ledger as beancount text, that can be loaded again to give the same Ledger
+/// Headings are written whenever the section changes between Directives,
+/// and those in no section come first, before any heading
+pub fn print_ledger(ledger: &Ledger) -> String {
+    let mut out = print_options(&ledger.opts);
+    let mut section: &[String] = &[];
+    let (none, sectioned): (Vec<&Directive>, Vec<&Directive>) =
+        ledger.dirs.iter().partition(|d| d.section().is_empty());
+    for d in none.into_iter().chain(sectioned) {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&print_headings(section, d.section()));
+        section = d.section();
+        out.push_str(&format!("{d}\n"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_options() {
+        let opts = Options {
+            title: "My \"books\"".to_string(),
+            name_assets: "Aktiva".to_string(),
+            ..Default::default()
+        };
+        let want = "option \"title\" \"My \\\"books\\\"\"\noption \"name_assets\" \"Aktiva\"\n";
+        assert!(print_options(&opts) == want);
+    }
+
+    #[test]
+    fn test_print_headings() {
+        let path = |p: &[&str]| -> Vec<String> { p.iter().map(|s| s.to_string()).collect() };
+        let home = path(&["Home"]);
+        let flat = path(&["Home", "Rental flat"]);
+        let car = path(&["Home", "Car"]);
+        assert!(print_headings(&[], &flat) == "* Home\n** Rental flat\n");
+        assert!(print_headings(&flat, &car) == "** Car\n");
+        assert!(print_headings(&flat, &home) == "* Home\n");
+        assert!(print_headings(&car, &car).is_empty());
+        assert!(print_headings(&car, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_print_sections() {
+        let text = r#"
+2023-01-01 open Assets:Cash
+* Home
+** Rental flat
+2023-01-02 open Assets:Rent
+* Car
+2023-01-03 open Expenses:Car
+"#;
+        let ledger = crate::load(text.to_string());
+        let printed = print_ledger(&ledger);
+        let reloaded = crate::load(printed.clone());
+        let sections = |l: &Ledger| -> Vec<Vec<String>> {
+            l.dirs.iter().map(|d| d.section().to_vec()).collect()
+        };
+        assert!(sections(&reloaded) == sections(&ledger), "{printed}");
+        assert!(printed.find("Assets:Cash") < printed.find("* Home"));
+        assert!(!printed.lines().any(|l| l.trim() == "*"));
+        assert!(print_ledger(&reloaded) == printed);
+    }
+}
@@ -0,0 +1,1496 @@
+//! A query engine for a subset of the beancount query language (BQL)
+//!
+//! Queries run over the postings of a Ledger's Transactions, eg:
+//!
+//! ```text
+//! SELECT account, sum(position) FROM year = 2023 WHERE account ~ 'Expenses'
+//!   GROUP BY account ORDER BY account_sortkey(account)
+//! ```
+//!
+//! `FROM` filters whole Transactions, and can clamp the Ledger to a period
+//! with `OPEN ON` and `CLOSE ON`. `WHERE` filters the postings.
+//! `BALANCES` and `JOURNAL` are shorthands that expand to a `SELECT`.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::{Datelike, NaiveDate};
+use pest::iterators::Pair;
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use pest::Parser;
+use regex::{Regex, RegexBuilder};
+use rust_decimal::Decimal;
+
+use crate::data::{Amount, DebugLine, Directive, Options, Posting, Transaction};
+use crate::error::{BeanError, ErrorType};
+use crate::inventory::{self, Inventory, Position};
+use crate::ledger::Ledger;
+use crate::period;
+use crate::realization;
+
+mod grammar {
+    use pest_derive::Parser;
+
+    #[derive(Parser)]
+    #[grammar = "query.pest"]
+    pub struct QueryParser;
+}
+
+use grammar::{QueryParser, Rule};
+
+/// The columns of the postings table
+pub const COLUMNS: &[&str] = &[
+    "date",
+    "year",
+    "month",
+    "day",
+    "flag",
+    "payee",
+    "narration",
+    "description",
+    "tags",
+    "links",
+    "section",
+    "lineno",
+    "account",
+    "other_accounts",
+    "position",
+    "number",
+    "currency",
+    "cost_number",
+    "cost_currency",
+    "price",
+    "weight",
+    "balance",
+];
+
+/// The columns selected by `SELECT *`
+const WILDCARD: &[&str] = &["date", "flag", "payee", "narration", "account", "position"];
+
+/// Functions and their number of arguments
+pub const FUNCTIONS: &[(&str, usize)] = &[
+    ("units", 1),
+    ("cost", 1),
+    ("number", 1),
+    ("currency", 1),
+    ("only", 2),
+    ("year", 1),
+    ("month", 1),
+    ("day", 1),
+    ("account_sortkey", 1),
+    ("parent", 1),
+    ("leaf", 1),
+    ("root", 2),
+    ("length", 1),
+    ("str", 1),
+    ("upper", 1),
+    ("lower", 1),
+    ("abs", 1),
+    ("coalesce", 2),
+    ("meta", 1),
+];
+
+/// Functions that combine the rows of a group
+pub const AGGREGATES: &[&str] = &["sum", "count", "first", "last", "min", "max"];
+
+/// A value in a query, either from the Ledger or computed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Decimal),
+    Str(String),
+    Date(NaiveDate),
+    Amount(Amount),
+    Position(Position),
+    Inventory(Inventory),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => !n.is_zero(),
+            Value::Str(s) => !s.is_empty(),
+            Value::Inventory(inv) => !inv.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            _ => true,
+        }
+    }
+
+    /// Whether the value is a quantity, which is right-aligned when rendered
+    fn is_quantity(&self) -> bool {
+        matches!(
+            self,
+            Value::Number(_) | Value::Amount(_) | Value::Position(_) | Value::Inventory(_)
+        )
+    }
+
+    /// Values of different types are ordered by type, with Null first
+    fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Number(a), Value::Number(b)) => a.cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.cmp(b),
+            (Value::Amount(a), Value::Amount(b)) => (&a.ccy, a.number).cmp(&(&b.ccy, b.number)),
+            (Value::Position(a), Value::Position(b)) => {
+                (&a.units.ccy, a.units.number).cmp(&(&b.units.ccy, b.units.number))
+            }
+            (Value::List(a), Value::List(b)) => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| a.compare(b))
+                .find(|o| o.is_ne())
+                .unwrap_or(a.len().cmp(&b.len())),
+            (a, b) if a.rank() != b.rank() => a.rank().cmp(&b.rank()),
+            (a, b) => a.to_string().cmp(&b.to_string()),
+        }
+    }
+
+    fn rank(&self) -> usize {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::Str(_) => 3,
+            Value::Date(_) => 4,
+            Value::Amount(_) => 5,
+            Value::Position(_) => 6,
+            Value::Inventory(_) => 7,
+            Value::List(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(true) => write!(f, "TRUE"),
+            Value::Bool(false) => write!(f, "FALSE"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Str(s) => write!(f, "{s}"),
+            Value::Date(d) => write!(f, "{d}"),
+            Value::Amount(a) => write!(f, "{a}"),
+            Value::Position(p) => write!(f, "{p}"),
+            Value::Inventory(inv) => write!(f, "{inv}"),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", items.join(","))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Match,
+    In,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Literal(Value),
+    Column(String),
+    /// A function call, with no arguments for `count(*)`
+    Call(String, Vec<Expr>),
+    Tuple(Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    /// Whether the expression is (true) or is not (false) NULL
+    IsNull(Box<Expr>, bool),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// A `~` against a literal regex, compiled once when parsing
+    Match(Box<Expr>, Regex),
+}
+
+impl Expr {
+    fn is_aggregate(&self) -> bool {
+        self.any(&|e| matches!(e, Expr::Call(name, _) if AGGREGATES.contains(&name.as_str())))
+    }
+
+    fn uses_column(&self, column: &str) -> bool {
+        self.any(&|e| matches!(e, Expr::Column(c) if c == column))
+    }
+
+    /// Whether `pred` holds for this expression or any inside it
+    fn any(&self, pred: &dyn Fn(&Expr) -> bool) -> bool {
+        pred(self)
+            || match self {
+                Expr::Literal(_) | Expr::Column(_) => false,
+                Expr::Call(_, args) | Expr::Tuple(args) => args.iter().any(|a| a.any(pred)),
+                Expr::Not(e) | Expr::Neg(e) | Expr::IsNull(e, _) | Expr::Match(e, _) => e.any(pred),
+                Expr::Binary(_, l, r) => l.any(pred) || r.any(pred),
+            }
+    }
+
+    /// Check that the columns and functions exist
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Expr::Column(c) if !COLUMNS.contains(&c.as_str()) => {
+                Err(format!("Unknown column: {c}"))
+            }
+            Expr::Call(name, args) if AGGREGATES.contains(&name.as_str()) => {
+                match (name.as_str(), args.len()) {
+                    ("count", 0 | 1) | (_, 1) => (),
+                    _ => return Err(format!("Wrong number of arguments to {name}")),
+                }
+                if args.iter().any(Expr::is_aggregate) {
+                    return Err(format!("Aggregates can't be nested in {name}"));
+                }
+                args.iter().try_for_each(Expr::validate)
+            }
+            Expr::Call(name, args) => match FUNCTIONS.iter().find(|(f, _)| f == name) {
+                None => Err(format!("Unknown function: {name}")),
+                Some((_, n)) if *n != args.len() => {
+                    Err(format!("{name} takes {n} argument(s), not {}", args.len()))
+                }
+                Some(_) => args.iter().try_for_each(Expr::validate),
+            },
+            Expr::Tuple(args) => args.iter().try_for_each(Expr::validate),
+            Expr::Not(e) | Expr::Neg(e) | Expr::IsNull(e, _) | Expr::Match(e, _) => e.validate(),
+            Expr::Binary(_, l, r) => l.validate().and(r.validate()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            BinOp::Or => "OR",
+            BinOp::And => "AND",
+            BinOp::Eq => "=",
+            BinOp::Neq => "!=",
+            BinOp::Lt => "<",
+            BinOp::Lte => "<=",
+            BinOp::Gt => ">",
+            BinOp::Gte => ">=",
+            BinOp::Match => "~",
+            BinOp::In => "IN",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+        };
+        write!(f, "{op}")
+    }
+}
+
+/// Expressions are written back out as BQL, with every operation bracketed
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |exprs: &[Expr]| {
+            let exprs: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+            exprs.join(", ")
+        };
+        match self {
+            Expr::Literal(Value::Null) => write!(f, "NULL"),
+            Expr::Literal(Value::Str(s)) => write!(f, "'{s}'"),
+            Expr::Literal(v) => write!(f, "{v}"),
+            Expr::Column(c) => write!(f, "{c}"),
+            Expr::Call(name, args) if args.is_empty() => write!(f, "{name}(*)"),
+            Expr::Call(name, args) => write!(f, "{name}({args})", args = join(args)),
+            Expr::Tuple(items) => write!(f, "({items})", items = join(items)),
+            Expr::Not(e) => write!(f, "(NOT {e})"),
+            Expr::Neg(e) => write!(f, "-{e}"),
+            Expr::IsNull(e, true) => write!(f, "({e} IS NULL)"),
+            Expr::IsNull(e, false) => write!(f, "({e} IS NOT NULL)"),
+            Expr::Binary(op, l, r) => write!(f, "({l} {op} {r})"),
+            Expr::Match(e, re) => write!(f, "({e} ~ '{re}')"),
+        }
+    }
+}
+
+/// A selected column, named by its alias or as written
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub expr: Expr,
+    pub name: String,
+}
+
+/// The `FROM` clause, which applies to Transactions rather than postings
+#[derive(Clone, Debug, Default)]
+pub struct FromClause {
+    pub filter: Option<Expr>,
+    /// Summarize everything before this date into opening balances
+    pub open: Option<NaiveDate>,
+    /// Drop everything on or after this date
+    pub close: Option<NaiveDate>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Select {
+    pub distinct: bool,
+    pub targets: Vec<Target>,
+    pub from: FromClause,
+    pub filter: Option<Expr>,
+    pub group_by: Option<Vec<Expr>>,
+    /// Each expression and whether it is descending
+    pub order_by: Vec<(Expr, bool)>,
+    pub limit: Option<usize>,
+}
+
+impl Select {
+    /// The expressions rows are grouped by, if the query aggregates
+    /// Without GROUP BY, these are the targets that aren't aggregates
+    pub fn group_keys(&self) -> Option<Vec<&Expr>> {
+        if let Some(exprs) = &self.group_by {
+            return Some(exprs.iter().collect());
+        }
+        if !self.targets.iter().any(|t| t.expr.is_aggregate()) {
+            return None;
+        }
+        let keys = self.targets.iter().map(|t| &t.expr);
+        Some(keys.filter(|e| !e.is_aggregate()).collect())
+    }
+
+    /// The query as it will be run, one clause per line, with the
+    /// precedence of operators shown by brackets and any implied GROUP BY
+    pub fn explain(&self) -> String {
+        let join = |exprs: Vec<String>| exprs.join(", ");
+        let targets = self.targets.iter().map(|t| match t.expr.to_string() {
+            expr if expr == t.name => expr,
+            expr => format!("{expr} AS {name}", name = t.name),
+        });
+        let mut lines = vec![format!(
+            "SELECT {distinct}{targets}",
+            distinct = if self.distinct { "DISTINCT " } else { "" },
+            targets = join(targets.collect()),
+        )];
+        let mut from = Vec::new();
+        if let Some(filter) = &self.from.filter {
+            from.push(filter.to_string());
+        }
+        if let Some(date) = self.from.open {
+            from.push(format!("OPEN ON {date}"));
+        }
+        if let Some(date) = self.from.close {
+            from.push(format!("CLOSE ON {date}"));
+        }
+        if !from.is_empty() {
+            lines.push(format!("FROM {}", from.join(" ")));
+        }
+        if let Some(filter) = &self.filter {
+            lines.push(format!("WHERE {filter}"));
+        }
+        if let Some(keys) = self.group_keys().filter(|k| !k.is_empty()) {
+            lines.push(format!(
+                "GROUP BY {}",
+                join(keys.iter().map(|e| e.to_string()).collect())
+            ));
+        }
+        if !self.order_by.is_empty() {
+            let keys = self.order_by.iter().map(|(e, desc)| match desc {
+                true => format!("{e} DESC"),
+                false => e.to_string(),
+            });
+            lines.push(format!("ORDER BY {}", join(keys.collect())));
+        }
+        if let Some(limit) = self.limit {
+            lines.push(format!("LIMIT {limit}"));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Quote a string for JSON
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+impl Value {
+    /// The value as JSON, with numbers kept exact and anything else that
+    /// isn't a list written as a string
+    pub fn to_json(&self) -> String {
+        match self {
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(Value::to_json).collect();
+                format!("[{}]", items.join(", "))
+            }
+            v => json_string(&v.to_string()),
+        }
+    }
+}
+
+/// The result of a query, or any other report with a row per item
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    /// Render as aligned columns with a header,
+    /// with numbers and amounts right-aligned
+    pub fn render(&self) -> String {
+        let right: Vec<bool> = (0..self.columns.len())
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .map(|r| &r[i])
+                    .find(|v| **v != Value::Null)
+                    .is_some_and(Value::is_quantity)
+            })
+            .collect();
+        let mut rows = vec![self.columns.clone()];
+        for row in &self.rows {
+            rows.push(row.iter().map(|v| v.to_string()).collect());
+        }
+        realization::format_aligned(&rows, &right)
+    }
+
+    /// Render as CSV with a header
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let header: Vec<String> = self.columns.iter().map(|c| csv_field(c)).collect();
+        out.push_str(&header.join(","));
+        out.push('\n');
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter().map(|v| csv_field(&v.to_string())).collect();
+            out.push_str(&cells.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render as a JSON array with an object per row, keyed by column
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let fields: Vec<String> = self
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .map(|(c, v)| format!("{}: {}", json_string(c), v.to_json()))
+                    .collect();
+                format!("  {{{}}}", fields.join(", "))
+            })
+            .collect();
+        match rows.is_empty() {
+            true => "[]\n".to_string(),
+            false => format!("[\n{}\n]\n", rows.join(",\n")),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> BeanError {
+    let msg = format!("Invalid query: {msg}");
+    BeanError::new(ErrorType::InvalidQuery, &DebugLine::default(), &msg, None)
+}
+
+fn pratt() -> PrattParser<Rule> {
+    // Operators are listed from lowest to highest precedence
+    PrattParser::new()
+        .op(Op::infix(Rule::or, Assoc::Left))
+        .op(Op::infix(Rule::and, Assoc::Left))
+        .op(Op::prefix(Rule::not))
+        .op(Op::infix(Rule::eq, Assoc::Left)
+            | Op::infix(Rule::neq, Assoc::Left)
+            | Op::infix(Rule::lt, Assoc::Left)
+            | Op::infix(Rule::lte, Assoc::Left)
+            | Op::infix(Rule::gt, Assoc::Left)
+            | Op::infix(Rule::gte, Assoc::Left)
+            | Op::infix(Rule::matches, Assoc::Left)
+            | Op::infix(Rule::in_op, Assoc::Left))
+        .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
+        .op(Op::infix(Rule::mul, Assoc::Left) | Op::infix(Rule::div, Assoc::Left))
+        .op(Op::prefix(Rule::neg))
+        .op(Op::postfix(Rule::is_null) | Op::postfix(Rule::is_not_null))
+}
+
+fn regex(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("Invalid regex '{pattern}': {e}"))
+}
+
+fn parse_string(pair: Pair<Rule>) -> String {
+    pair.into_inner().next().unwrap().as_str().to_string()
+}
+
+fn parse_date(pair: Pair<Rule>) -> Result<NaiveDate, String> {
+    let text = pair.as_str();
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|_| format!("Invalid date: {text}"))
+}
+
+fn parse_expr(pair: Pair<Rule>, pratt: &PrattParser<Rule>) -> Result<Expr, String> {
+    pratt
+        .map_primary(|primary| {
+            let expr = match primary.as_rule() {
+                Rule::expr => parse_expr(primary, pratt)?,
+                Rule::tuple => Expr::Tuple(
+                    primary
+                        .into_inner()
+                        .map(|p| parse_expr(p, pratt))
+                        .collect::<Result<_, _>>()?,
+                ),
+                Rule::function => {
+                    let mut pairs = primary.into_inner();
+                    let name = pairs.next().unwrap().as_str().to_lowercase();
+                    let args = pairs
+                        .filter(|p| p.as_rule() == Rule::expr)
+                        .map(|p| parse_expr(p, pratt))
+                        .collect::<Result<_, _>>()?;
+                    Expr::Call(name, args)
+                }
+                Rule::column => Expr::Column(primary.as_str().to_lowercase()),
+                Rule::date => Expr::Literal(Value::Date(parse_date(primary)?)),
+                Rule::number => {
+                    let text = primary.as_str();
+                    let number = text
+                        .parse()
+                        .map_err(|_| format!("Invalid number: {text}"))?;
+                    Expr::Literal(Value::Number(number))
+                }
+                Rule::string => Expr::Literal(Value::Str(parse_string(primary))),
+                Rule::boolean => {
+                    Expr::Literal(Value::Bool(primary.as_str().eq_ignore_ascii_case("true")))
+                }
+                Rule::null => Expr::Literal(Value::Null),
+                rule => unreachable!("Unexpected {rule:?} in query expression"),
+            };
+            Ok(expr)
+        })
+        .map_prefix(|op, rhs| match op.as_rule() {
+            Rule::not => Ok(Expr::Not(Box::new(rhs?))),
+            _ => Ok(Expr::Neg(Box::new(rhs?))),
+        })
+        .map_postfix(|lhs, op| Ok(Expr::IsNull(Box::new(lhs?), op.as_rule() == Rule::is_null)))
+        .map_infix(|lhs, op, rhs| {
+            let (lhs, rhs) = (Box::new(lhs?), rhs?);
+            let op = match op.as_rule() {
+                Rule::or => BinOp::Or,
+                Rule::and => BinOp::And,
+                Rule::eq => BinOp::Eq,
+                Rule::neq => BinOp::Neq,
+                Rule::lt => BinOp::Lt,
+                Rule::lte => BinOp::Lte,
+                Rule::gt => BinOp::Gt,
+                Rule::gte => BinOp::Gte,
+                Rule::in_op => BinOp::In,
+                Rule::add => BinOp::Add,
+                Rule::sub => BinOp::Sub,
+                Rule::mul => BinOp::Mul,
+                Rule::div => BinOp::Div,
+                _ => match rhs {
+                    Expr::Literal(Value::Str(pattern)) => {
+                        return Ok(Expr::Match(lhs, regex(&pattern)?))
+                    }
+                    _ => BinOp::Match,
+                },
+            };
+            Ok(Expr::Binary(op, lhs, Box::new(rhs)))
+        })
+        .parse(pair.into_inner())
+}
+
+/// An ORDER BY or GROUP BY expression may refer to a target
+/// by its position (from 1) or its name
+fn resolve(expr: Expr, targets: &[Target]) -> Result<Expr, String> {
+    match &expr {
+        Expr::Literal(Value::Number(n)) => {
+            let i = n.to_string().parse::<usize>().unwrap_or(0);
+            match i.checked_sub(1).and_then(|i| targets.get(i)) {
+                Some(t) => Ok(t.expr.clone()),
+                None => Err(format!("No column {n} to refer to")),
+            }
+        }
+        Expr::Column(name) if !COLUMNS.contains(&name.as_str()) => {
+            match targets.iter().find(|t| t.name.eq_ignore_ascii_case(name)) {
+                Some(t) => Ok(t.expr.clone()),
+                None => Ok(expr),
+            }
+        }
+        _ => Ok(expr),
+    }
+}
+
+fn parse_select(entry: Pair<Rule>) -> Result<Select, String> {
+    let pratt = pratt();
+    let mut select = Select::default();
+    for pair in entry.into_inner() {
+        match pair.as_rule() {
+            Rule::distinct => select.distinct = true,
+            Rule::wildcard => {
+                select.targets = WILDCARD
+                    .iter()
+                    .map(|c| Target {
+                        expr: Expr::Column(c.to_string()),
+                        name: c.to_string(),
+                    })
+                    .collect()
+            }
+            Rule::target => {
+                let mut pairs = pair.into_inner();
+                let expr = pairs.next().unwrap();
+                let name = match pairs.next() {
+                    Some(alias) => alias.as_str().to_string(),
+                    None => expr.as_str().trim().to_string(),
+                };
+                let expr = parse_expr(expr, &pratt)?;
+                select.targets.push(Target { expr, name });
+            }
+            Rule::from => {
+                for pair in pair.into_inner() {
+                    match pair.as_rule() {
+                        Rule::from_expr => {
+                            let expr = pair.into_inner().next().unwrap();
+                            select.from.filter = Some(parse_expr(expr, &pratt)?);
+                        }
+                        Rule::open_on => {
+                            let date = pair.into_inner().next().unwrap();
+                            select.from.open = Some(parse_date(date)?);
+                        }
+                        _ => {
+                            let date = pair.into_inner().next().unwrap();
+                            select.from.close = Some(parse_date(date)?);
+                        }
+                    }
+                }
+            }
+            Rule::where_clause => {
+                let expr = pair.into_inner().next().unwrap();
+                select.filter = Some(parse_expr(expr, &pratt)?);
+            }
+            Rule::group_by => {
+                let exprs = pair
+                    .into_inner()
+                    .map(|p| resolve(parse_expr(p, &pratt)?, &select.targets))
+                    .collect::<Result<_, _>>()?;
+                select.group_by = Some(exprs);
+            }
+            Rule::order_by => {
+                for key in pair.into_inner() {
+                    let mut pairs = key.into_inner();
+                    let expr = parse_expr(pairs.next().unwrap(), &pratt)?;
+                    let expr = resolve(expr, &select.targets)?;
+                    let desc = pairs.next().is_some_and(|p| p.as_rule() == Rule::desc);
+                    select.order_by.push((expr, desc));
+                }
+            }
+            Rule::limit => {
+                let text = pair.into_inner().next().unwrap().as_str();
+                select.limit = Some(text.parse().map_err(|_| format!("Invalid limit: {text}"))?);
+            }
+            _ => (),
+        }
+    }
+    Ok(select)
+}
+
+/// The text of the clauses that a shorthand passes on to its select,
+/// and the function given with `AT`, if any
+fn shorthand_clauses(entry: Pair<Rule>) -> (Option<String>, Option<String>, Vec<String>) {
+    let mut journal = None;
+    let mut at = None;
+    let mut clauses = Vec::new();
+    for pair in entry.into_inner() {
+        match pair.as_rule() {
+            Rule::string => journal = Some(pair.as_str().to_string()),
+            Rule::at => at = Some(pair.into_inner().next().unwrap().as_str().to_string()),
+            _ => clauses.push(pair.as_str().to_string()),
+        }
+    }
+    (journal, at, clauses)
+}
+
+/// Expand `BALANCES` and `JOURNAL` to the select they are short for
+fn expand(entry: Pair<Rule>) -> String {
+    let rule = entry.as_rule();
+    let (journal, at, clauses) = shorthand_clauses(entry);
+    let wrap = |column: &str| match &at {
+        Some(f) => format!("{f}({column})"),
+        None => column.to_string(),
+    };
+    let clauses = clauses.join(" ");
+    match rule {
+        Rule::balances => format!(
+            "SELECT account, sum({position}) {clauses} \
+             GROUP BY account ORDER BY account_sortkey(account)",
+            position = wrap("position"),
+        ),
+        _ => {
+            let filter = match journal {
+                Some(pattern) => format!("WHERE account ~ {pattern}"),
+                None => String::new(),
+            };
+            format!(
+                "SELECT date, flag, payee, narration, account, {position}, {balance} \
+                 {clauses} {filter}",
+                position = wrap("position"),
+                balance = wrap("balance"),
+            )
+        }
+    }
+}
+
+/// Parse a query, checking that the columns and functions it uses exist
+pub fn parse(text: &str) -> Result<Select, BeanError> {
+    let mut pairs = QueryParser::parse(Rule::query, text).map_err(|e| {
+        let col = match e.line_col {
+            pest::error::LineColLocation::Pos((_, col)) => col,
+            pest::error::LineColLocation::Span((_, col), _) => col,
+        };
+        invalid(&format!("syntax error at column {col} in: {text}"))
+    })?;
+    let entry = pairs.next().unwrap().into_inner().next().unwrap();
+    let select = match entry.as_rule() {
+        Rule::select => parse_select(entry).map_err(|e| invalid(&e))?,
+        _ => return parse(&expand(entry)),
+    };
+    let exprs = select
+        .targets
+        .iter()
+        .map(|t| &t.expr)
+        .chain(select.from.filter.iter())
+        .chain(select.filter.iter())
+        .chain(select.group_by.iter().flatten())
+        .chain(select.order_by.iter().map(|(e, _)| e));
+    for expr in exprs {
+        expr.validate().map_err(|e| invalid(&e))?;
+    }
+    for expr in select.from.filter.iter().chain(select.filter.iter()) {
+        if expr.is_aggregate() {
+            return Err(invalid("aggregates can't be used to filter"));
+        }
+    }
+    if let Some(group_by) = &select.group_by {
+        // Otherwise only the first row of each group would be shown
+        let keys: Vec<String> = group_by.iter().map(|e| e.to_string()).collect();
+        for target in &select.targets {
+            let expr = &target.expr;
+            let uses_columns = expr.any(&|e| matches!(e, Expr::Column(_)));
+            if uses_columns && !expr.is_aggregate() && !keys.contains(&expr.to_string()) {
+                let msg = format!("{} must be aggregated or in the GROUP BY", target.name);
+                return Err(invalid(&msg));
+            }
+        }
+    }
+    Ok(select)
+}
+
+/// A posting in the postings table, or just its Transaction when filtering with `FROM`
+struct Row<'a> {
+    tx: &'a Transaction,
+    posting: Option<&'a Posting>,
+    /// The running balance of the selected postings, if the query uses it
+    balance: Option<Inventory>,
+}
+
+fn text(value: Option<&str>) -> Value {
+    value.map_or(Value::Null, |s| Value::Str(s.to_string()))
+}
+
+fn list<'a>(items: impl Iterator<Item = &'a String>) -> Value {
+    Value::List(items.map(|s| Value::Str(s.clone())).collect())
+}
+
+fn column(name: &str, row: &Row) -> Value {
+    let tx = row.tx;
+    let amount = row.posting.and_then(|p| p.amount.as_ref());
+    let cost = row
+        .posting
+        .and_then(|p| p.cost.as_ref())
+        .and_then(|c| c.amount.as_ref());
+    match name {
+        "date" => Value::Date(tx.date),
+        "year" => Value::Number(tx.date.year().into()),
+        "month" => Value::Number(tx.date.month().into()),
+        "day" => Value::Number(tx.date.day().into()),
+        "flag" if tx.ty == "txn" => Value::Str("*".to_string()),
+        "flag" => Value::Str(tx.ty.clone()),
+        "payee" => text(tx.payee.as_deref()),
+        "narration" => Value::Str(tx.narration.clone()),
+        "description" => match &tx.payee {
+            Some(payee) => Value::Str(format!("{payee} | {}", tx.narration)),
+            None => Value::Str(tx.narration.clone()),
+        },
+        "tags" => list(tx.tags.iter()),
+        "links" => list(tx.links.iter()),
+        "section" => list(tx.section.iter()),
+        "lineno" => Value::Number(tx.debug.line.into()),
+        "account" => text(row.posting.map(|p| p.account.as_str())),
+        "other_accounts" => match row.posting {
+            Some(posting) => list(
+                tx.postings
+                    .iter()
+                    .filter(|p| !std::ptr::eq(*p, posting))
+                    .map(|p| &p.account),
+            ),
+            None => Value::Null,
+        },
+        "position" => row
+            .posting
+            .and_then(Position::from_posting)
+            .map_or(Value::Null, Value::Position),
+        "number" => amount.map_or(Value::Null, |a| Value::Number(a.number)),
+        "currency" => text(amount.map(|a| a.ccy.as_str())),
+        "cost_number" => cost.map_or(Value::Null, |c| Value::Number(c.number)),
+        "cost_currency" => text(cost.map(|c| c.ccy.as_str())),
+        "price" => row
+            .posting
+            .and_then(|p| p.price.as_ref())
+            .and_then(|p| Some(Amount::new(p.number?, p.ccy.clone())))
+            .map_or(Value::Null, Value::Amount),
+        "weight" => row
+            .posting
+            .and_then(inventory::weight)
+            .map_or(Value::Null, Value::Amount),
+        "balance" => row.balance.clone().map_or(Value::Null, Value::Inventory),
+        _ => Value::Null,
+    }
+}
+
+/// The value of a `meta('key')` call: the posting's metadata, then the Transaction's
+fn meta(key: &Value, row: &Row) -> Value {
+    let Value::Str(key) = key else {
+        return Value::Null;
+    };
+    let posting_meta = row.posting.map(|p| p.meta.as_slice()).unwrap_or_default();
+    posting_meta
+        .iter()
+        .chain(&row.tx.meta)
+        .find(|m| &m.key == key)
+        .map_or(Value::Null, |m| Value::Str(m.val.as_str().to_string()))
+}
+
+/// Evaluate an expression, leaving columns, aggregates and `meta`
+/// (which all depend on the rows) to `leaf`
+fn eval(expr: &Expr, leaf: &dyn Fn(&Expr) -> Value, opts: &Options) -> Value {
+    match expr {
+        Expr::Literal(v) => v.clone(),
+        Expr::Column(_) => leaf(expr),
+        Expr::Call(name, _) if name == "meta" || AGGREGATES.contains(&name.as_str()) => leaf(expr),
+        Expr::Call(name, args) => {
+            let args: Vec<Value> = args.iter().map(|a| eval(a, leaf, opts)).collect();
+            call(name, &args, opts)
+        }
+        Expr::Tuple(items) => Value::List(items.iter().map(|e| eval(e, leaf, opts)).collect()),
+        Expr::Not(e) => Value::Bool(!eval(e, leaf, opts).is_true()),
+        Expr::Neg(e) => match eval(e, leaf, opts) {
+            Value::Number(n) => Value::Number(-n),
+            Value::Amount(a) => Value::Amount(Amount::new(-a.number, a.ccy)),
+            _ => Value::Null,
+        },
+        Expr::IsNull(e, is_null) => Value::Bool((eval(e, leaf, opts) == Value::Null) == *is_null),
+        Expr::Match(e, re) => match eval(e, leaf, opts) {
+            Value::Null => Value::Bool(false),
+            v => Value::Bool(re.is_match(&v.to_string())),
+        },
+        Expr::Binary(op, l, r) => binary(*op, eval(l, leaf, opts), eval(r, leaf, opts)),
+    }
+}
+
+fn eval_row(expr: &Expr, row: &Row, opts: &Options) -> Value {
+    let leaf = |e: &Expr| match e {
+        Expr::Column(name) => column(name, row),
+        Expr::Call(_, args) => meta(&eval_row(&args[0], row, opts), row),
+        _ => Value::Null,
+    };
+    eval(expr, &leaf, opts)
+}
+
+/// Evaluate over a group of rows, with anything that isn't aggregated
+/// taken from the first row
+fn eval_group(expr: &Expr, group: &[&Row], opts: &Options) -> Value {
+    let leaf = |e: &Expr| match e {
+        Expr::Call(name, args) if AGGREGATES.contains(&name.as_str()) => {
+            let values = group.iter().map(|row| match args.first() {
+                Some(arg) => eval_row(arg, row, opts),
+                None => Value::Bool(true),
+            });
+            aggregate(name, values)
+        }
+        _ => group
+            .first()
+            .map_or(Value::Null, |row| eval_row(e, row, opts)),
+    };
+    eval(expr, &leaf, opts)
+}
+
+fn aggregate(name: &str, values: impl Iterator<Item = Value>) -> Value {
+    let mut values = values.filter(|v| *v != Value::Null);
+    match name {
+        "count" => Value::Number(values.count().into()),
+        "first" => values.next().unwrap_or(Value::Null),
+        "last" => values.last().unwrap_or(Value::Null),
+        "min" => values.min_by(|a, b| a.compare(b)).unwrap_or(Value::Null),
+        "max" => values.max_by(|a, b| a.compare(b)).unwrap_or(Value::Null),
+        _ => {
+            let mut number: Option<Decimal> = None;
+            let mut inv: Option<Inventory> = None;
+            for v in values {
+                match v {
+                    Value::Number(n) => *number.get_or_insert_default() += n,
+                    Value::Amount(a) => inv.get_or_insert_default().add_amount(&a),
+                    Value::Position(p) => inv.get_or_insert_default().add(&p),
+                    Value::Inventory(i) => inv.get_or_insert_default().add_inventory(&i),
+                    _ => (),
+                }
+            }
+            match (inv, number) {
+                (Some(inv), _) => Value::Inventory(inv),
+                (None, Some(n)) => Value::Number(n),
+                (None, None) => Value::Null,
+            }
+        }
+    }
+}
+
+fn call(name: &str, args: &[Value], opts: &Options) -> Value {
+    let account = |v: &Value| match v {
+        Value::Str(s) => Some(s.clone()),
+        _ => None,
+    };
+    match (name, args) {
+        ("units", [Value::Position(p)]) => Value::Amount(p.units.clone()),
+        ("units", [Value::Inventory(inv)]) => Value::Inventory(inv.units()),
+        ("cost", [Value::Position(p)]) => Value::Amount(p.cost()),
+        ("cost", [Value::Inventory(inv)]) => Value::Inventory(inv.cost()),
+        ("units" | "cost", [v @ Value::Amount(_)]) => v.clone(),
+        ("number", [Value::Amount(a)]) => Value::Number(a.number),
+        ("number", [Value::Position(p)]) => Value::Number(p.units.number),
+        ("currency", [Value::Amount(a)]) => Value::Str(a.ccy.clone()),
+        ("currency", [Value::Position(p)]) => Value::Str(p.units.ccy.clone()),
+        ("only", [Value::Str(ccy), Value::Inventory(inv)]) => {
+            let number = inv
+                .positions()
+                .iter()
+                .filter(|p| &p.units.ccy == ccy)
+                .map(|p| p.units.number)
+                .sum();
+            Value::Amount(Amount::new(number, ccy.clone()))
+        }
+        ("year", [Value::Date(d)]) => Value::Number(d.year().into()),
+        ("month", [Value::Date(d)]) => Value::Number(d.month().into()),
+        ("day", [Value::Date(d)]) => Value::Number(d.day().into()),
+        ("account_sortkey", [Value::Str(acc)]) => {
+            let (rank, _) = opts.account_order(acc);
+            Value::Str(format!("{rank}-{acc}"))
+        }
+        ("parent", [v]) => match account(v).and_then(|a| Some(a.rsplit_once(':')?.0.to_string())) {
+            Some(parent) => Value::Str(parent),
+            None => Value::Null,
+        },
+        ("leaf", [v]) => text(account(v).as_deref().and_then(|a| a.rsplit(':').next())),
+        ("root", [Value::Str(acc), Value::Number(n)]) => {
+            let n = n.to_string().parse().unwrap_or(0);
+            let parts: Vec<&str> = acc.split(':').take(n).collect();
+            Value::Str(parts.join(":"))
+        }
+        ("length", [Value::Str(s)]) => Value::Number(s.chars().count().into()),
+        ("length", [Value::List(items)]) => Value::Number(items.len().into()),
+        ("str", [v]) => Value::Str(v.to_string()),
+        ("upper", [Value::Str(s)]) => Value::Str(s.to_uppercase()),
+        ("lower", [Value::Str(s)]) => Value::Str(s.to_lowercase()),
+        ("abs", [Value::Number(n)]) => Value::Number(n.abs()),
+        ("abs", [Value::Amount(a)]) => Value::Amount(Amount::new(a.number.abs(), a.ccy.clone())),
+        ("coalesce", [Value::Null, v]) => v.clone(),
+        ("coalesce", [v, _]) => v.clone(),
+        _ => Value::Null,
+    }
+}
+
+fn binary(op: BinOp, l: Value, r: Value) -> Value {
+    let cmp = || match (&l, &r) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (l, r) if l.rank() == r.rank() => Some(l.compare(r)),
+        _ => None,
+    };
+    match (op, &l, &r) {
+        (BinOp::Or, _, _) => Value::Bool(l.is_true() || r.is_true()),
+        (BinOp::And, _, _) => Value::Bool(l.is_true() && r.is_true()),
+        (BinOp::Eq, _, _) => Value::Bool(cmp().is_some_and(Ordering::is_eq)),
+        (BinOp::Neq, _, _) => Value::Bool(cmp().is_some_and(Ordering::is_ne)),
+        (BinOp::Lt, _, _) => Value::Bool(cmp().is_some_and(Ordering::is_lt)),
+        (BinOp::Lte, _, _) => Value::Bool(cmp().is_some_and(Ordering::is_le)),
+        (BinOp::Gt, _, _) => Value::Bool(cmp().is_some_and(Ordering::is_gt)),
+        (BinOp::Gte, _, _) => Value::Bool(cmp().is_some_and(Ordering::is_ge)),
+        (BinOp::Match, Value::Null, _) => Value::Bool(false),
+        (BinOp::Match, _, Value::Str(pattern)) => match regex(pattern) {
+            Ok(re) => Value::Bool(re.is_match(&l.to_string())),
+            Err(_) => Value::Null,
+        },
+        (BinOp::In, _, Value::List(items)) => Value::Bool(
+            items
+                .iter()
+                .any(|i| binary(BinOp::Eq, l.clone(), i.clone()).is_true()),
+        ),
+        (BinOp::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+        (BinOp::Sub, Value::Number(a), Value::Number(b)) => Value::Number(a - b),
+        (BinOp::Mul, Value::Number(a), Value::Number(b)) => Value::Number(a * b),
+        (BinOp::Div, Value::Number(a), Value::Number(b)) if !b.is_zero() => Value::Number(a / b),
+        (BinOp::Add | BinOp::Sub, Value::Amount(a), Value::Amount(b)) if a.ccy == b.ccy => {
+            let b = if op == BinOp::Add {
+                b.number
+            } else {
+                -b.number
+            };
+            Value::Amount(Amount::new(a.number + b, a.ccy.clone()))
+        }
+        (BinOp::Mul, Value::Amount(a), Value::Number(n)) => {
+            Value::Amount(Amount::new(a.number * n, a.ccy.clone()))
+        }
+        (BinOp::Div, Value::Amount(a), Value::Number(n)) if !n.is_zero() => {
+            Value::Amount(Amount::new(a.number / n, a.ccy.clone()))
+        }
+        (BinOp::Add, Value::Date(d), Value::Number(n)) => {
+            let days = n.to_string().parse().unwrap_or(0);
+            d.checked_add_signed(chrono::Duration::days(days))
+                .map_or(Value::Null, Value::Date)
+        }
+        (BinOp::Sub, Value::Date(a), Value::Date(b)) => Value::Number((*a - *b).num_days().into()),
+        _ => Value::Null,
+    }
+}
+
+/// Run a parsed query over the Transactions in the Ledger
+/// The Ledger should already be balanced, with Pads turned into Transactions
+pub fn execute(ledger: &Ledger, select: &Select) -> Table {
+    let clamped;
+    let (dirs, opts) = match (select.from.open, select.from.close) {
+        (None, None) => (&ledger.dirs, &ledger.opts),
+        (open, close) => {
+            clamped = period::clamp(ledger, open, close);
+            (&clamped.dirs, &clamped.opts)
+        }
+    };
+
+    let uses_balance = select.targets.iter().any(|t| t.expr.uses_column("balance"))
+        || select
+            .order_by
+            .iter()
+            .any(|(e, _)| e.uses_column("balance"));
+    let mut running = Inventory::default();
+    let mut rows: Vec<Row> = Vec::new();
+    for d in dirs {
+        let Directive::Transaction(tx) = d else {
+            continue;
+        };
+        if let Some(filter) = &select.from.filter {
+            let row = Row {
+                tx,
+                posting: None,
+                balance: None,
+            };
+            if !eval_row(filter, &row, opts).is_true() {
+                continue;
+            }
+        }
+        for posting in &tx.postings {
+            let mut row = Row {
+                tx,
+                posting: Some(posting),
+                balance: None,
+            };
+            if let Some(filter) = &select.filter {
+                if !eval_row(filter, &row, opts).is_true() {
+                    continue;
+                }
+            }
+            if uses_balance {
+                if let Some(pos) = Position::from_posting(posting) {
+                    running.add(&pos);
+                }
+                row.balance = Some(running.clone());
+            }
+            rows.push(row);
+        }
+    }
+
+    // Each output row and the values it is ordered by
+    let mut out: Vec<(Vec<Value>, Vec<Value>)> = Vec::new();
+    if let Some(keys) = select.group_keys() {
+        let mut groups: Vec<Vec<&Row>> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for row in &rows {
+            let key: Vec<Value> = keys.iter().map(|e| eval_row(e, row, opts)).collect();
+            let i = *index.entry(format!("{key:?}")).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[i].push(row);
+        }
+        if groups.is_empty() && keys.is_empty() {
+            groups.push(Vec::new());
+        }
+        for group in groups {
+            let cells = select
+                .targets
+                .iter()
+                .map(|t| eval_group(&t.expr, &group, opts))
+                .collect();
+            let order = select
+                .order_by
+                .iter()
+                .map(|(e, _)| eval_group(e, &group, opts))
+                .collect();
+            out.push((cells, order));
+        }
+    } else {
+        for row in &rows {
+            let cells = select
+                .targets
+                .iter()
+                .map(|t| eval_row(&t.expr, row, opts))
+                .collect();
+            let order = select
+                .order_by
+                .iter()
+                .map(|(e, _)| eval_row(e, row, opts))
+                .collect();
+            out.push((cells, order));
+        }
+    }
+
+    if select.distinct {
+        let mut seen: HashMap<String, ()> = HashMap::new();
+        out.retain(|(cells, _)| seen.insert(format!("{cells:?}"), ()).is_none());
+    }
+    out.sort_by(|(_, a), (_, b)| {
+        a.iter()
+            .zip(b)
+            .zip(&select.order_by)
+            .map(|((a, b), (_, desc))| match desc {
+                true => b.compare(a),
+                false => a.compare(b),
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    if let Some(limit) = select.limit {
+        out.truncate(limit);
+    }
+    Table {
+        columns: select.targets.iter().map(|t| t.name.clone()).collect(),
+        rows: out.into_iter().map(|(cells, _)| cells).collect(),
+    }
+}
+
+/// Parse and run a query over the Ledger
+pub fn run(ledger: &Ledger, text: &str) -> Result<Table, BeanError> {
+    Ok(execute(ledger, &parse(text)?))
+}
+
+/// Run the query stored in the Ledger's `query` directive with this name
+pub fn run_named(ledger: &Ledger, name: &str) -> Result<Table, BeanError> {
+    let query = ledger.dirs.iter().find_map(|d| match d {
+        Directive::Query(q) if q.name == name => Some(q),
+        _ => None,
+    });
+    let Some(query) = query else {
+        return Err(invalid(&format!("no query named '{name}'")));
+    };
+    run(ledger, &query.query).map_err(|mut err| {
+        err.debug = query.debug.clone();
+        err
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Invest
+2023-01-01 open Income:Job
+2023-01-01 open Expenses:Food
+2023-01-01 open Expenses:Rent
+
+2023-01-15 * "Work" "Salary"
+  Assets:Bank     1000 GBP
+  Income:Job
+
+2023-01-20 * "Shop" #food
+  Expenses:Food     20 GBP
+    item: "bread"
+  Assets:Bank
+
+2023-02-01 * "Rent"
+  Expenses:Rent    500 GBP
+  Assets:Bank
+
+2023-02-15 * "Shop" #food
+  Expenses:Food     30 GBP
+  Assets:Bank
+
+2023-03-01 * "Buy shares"
+  Assets:Invest     2 GOOG {100 GBP}
+  Assets:Bank    -200 GBP
+
+2023-03-05 query "food" "SELECT date, position WHERE account ~ 'Food'"
+"#;
+
+    fn ledger() -> Ledger {
+        crate::load(TEXT.to_string())
+    }
+
+    fn query(text: &str) -> Table {
+        run(&ledger(), text).unwrap()
+    }
+
+    fn strings(table: &Table) -> Vec<Vec<String>> {
+        table
+            .rows
+            .iter()
+            .map(|r| r.iter().map(|v| v.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let select =
+            parse("select account, sum(position) as total group by 1 order by total desc").unwrap();
+        assert!(select.targets.len() == 2);
+        assert!(select.targets[1].name == "total");
+        assert!(matches!(&select.group_by.unwrap()[0], Expr::Column(c) if c == "account"));
+        assert!(matches!(&select.order_by[0], (Expr::Call(f, _), true) if f == "sum"));
+
+        let select = parse(
+            "SELECT * FROM year = 2023 OPEN ON 2023-02-01 WHERE NOT account ~ 'Bank' LIMIT 2",
+        )
+        .unwrap();
+        assert!(select.targets.len() == WILDCARD.len());
+        assert!(select.from.filter.is_some());
+        assert!(select.from.open == NaiveDate::from_ymd_opt(2023, 2, 1));
+        assert!(matches!(select.filter, Some(Expr::Not(_))));
+        assert!(select.limit == Some(2));
+    }
+
+    #[test]
+    fn test_explain() {
+        let select =
+            parse("BALANCES AT cost FROM year = 2023 WHERE account ~ 'Bank' OR number > 1 * 2")
+                .unwrap();
+        let want = "\
+SELECT account, sum(cost(position))
+FROM (year = 2023)
+WHERE ((account ~ 'Bank') OR (number > (1 * 2)))
+GROUP BY account
+ORDER BY account_sortkey(account)";
+        assert_eq!(select.explain(), want);
+        let select =
+            parse("SELECT count(*) AS n, account WHERE NOT payee IS NULL LIMIT 1").unwrap();
+        let want = "\
+SELECT count(*) AS n, account
+WHERE (NOT (payee IS NULL))
+GROUP BY account
+LIMIT 1";
+        assert_eq!(select.explain(), want);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for text in [
+            "SELECT",
+            "SELECT account FROM",
+            "SELECT nope",
+            "SELECT account WHERE sum(number) > 0",
+            "SELECT sum(sum(number))",
+            "SELECT year(date, date)",
+            "SELECT account WHERE account ~ '('",
+            "SELECT account ORDER BY 2",
+            "SELECT account, position GROUP BY account",
+            "SELECT date, sum(number) GROUP BY account",
+        ] {
+            let err = parse(text).unwrap_err();
+            assert!(err.ty == ErrorType::InvalidQuery, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let table = query(
+            "SELECT date, narration, number WHERE account ~ 'expenses:food' ORDER BY date DESC",
+        );
+        assert!(table.columns == vec!["date", "narration", "number"]);
+        assert!(
+            strings(&table)
+                == vec![
+                    vec!["2023-02-15", "Shop", "30"],
+                    vec!["2023-01-20", "Shop", "20"],
+                ]
+        );
+
+        let table = query("SELECT DISTINCT payee WHERE 'food' IN tags");
+        assert!(strings(&table) == vec![vec![""]]);
+        let table = query("SELECT meta('item'), other_accounts WHERE meta('item') IS NOT NULL");
+        assert!(strings(&table) == vec![vec!["bread", "Assets:Bank"]]);
+        let table = query("SELECT account FROM month = 2 WHERE number > 0");
+        assert!(strings(&table) == vec![vec!["Expenses:Rent"], vec!["Expenses:Food"]]);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let table = query(
+            "SELECT root(account, 1) AS type, sum(position), count(*) \
+             WHERE account ~ '^(Income|Expenses)' GROUP BY type ORDER BY type",
+        );
+        assert!(
+            strings(&table)
+                == vec![
+                    vec!["Expenses", "550 GBP", "3"],
+                    vec!["Income", "-1000 GBP", "1"],
+                ]
+        );
+        let table =
+            query("SELECT year(date), month(date), sum(number) WHERE account = 'Assets:Bank'");
+        assert!(
+            strings(&table)
+                == vec![
+                    vec!["2023", "1", "980"],
+                    vec!["2023", "2", "-530"],
+                    vec!["2023", "3", "-200"]
+                ]
+        );
+        let table =
+            query("SELECT cost(sum(position)), units(sum(position)) WHERE account ~ 'Invest'");
+        assert!(strings(&table) == vec![vec!["200 GBP", "2 GOOG"]]);
+    }
+
+    #[test]
+    fn test_aggregate_sale() {
+        let text = r#"
+2023-01-01 open Assets:Invest
+2023-01-01 open Assets:Bank
+2023-01-01 open Income:Gains
+
+2023-01-10 * "Buy"
+  Assets:Invest     10 GOOG {50 GBP}
+  Assets:Bank     -500 GBP
+
+2023-02-10 * "Buy"
+  Assets:Invest     10 GOOG {60 GBP}
+  Assets:Bank     -600 GBP
+
+2023-03-10 * "Sell"
+  Assets:Invest     -5 GOOG {}
+  Assets:Bank      250 GBP
+
+2023-04-10 * "Sell at a gain"
+  Assets:Invest     -5 GOOG {} @ 70 GBP
+  Assets:Bank
+  Income:Gains     -100 GBP
+"#;
+        let ledger = crate::load(text.to_string());
+        let table = run(
+            &ledger,
+            "SELECT sum(position), cost(sum(position)), units(sum(position)) \
+             WHERE account = 'Assets:Invest'",
+        )
+        .unwrap();
+        assert!(strings(&table) == vec![vec!["10 GOOG {60 GBP}", "600 GBP", "10 GOOG"]]);
+        // The blank leg gets the 350 GBP of the sale, not 5 GOOG
+        let table = run(
+            &ledger,
+            "SELECT account, sum(weight), cost(sum(position)) GROUP BY account ORDER BY account",
+        )
+        .unwrap();
+        assert!(
+            strings(&table)
+                == vec![
+                    vec!["Assets:Bank", "-500 GBP", "-500 GBP"],
+                    vec!["Assets:Invest", "600 GBP", "600 GBP"],
+                    vec!["Income:Gains", "-100 GBP", "-100 GBP"],
+                ]
+        );
+    }
+
+    #[test]
+    fn test_shorthands() {
+        let table = query("BALANCES");
+        assert!(table.columns == vec!["account", "sum(position)"]);
+        assert!(table.rows[0][0] == Value::Str("Assets:Bank".to_string()));
+        assert!(table.rows.last().unwrap()[0] == Value::Str("Expenses:Rent".to_string()));
+        let table = query("BALANCES AT cost WHERE account ~ 'Invest'");
+        assert!(strings(&table) == vec![vec!["Assets:Invest", "200 GBP"]]);
+
+        let table = query("JOURNAL 'Food'");
+        assert!(table.rows.len() == 2);
+        assert!(table.rows[1][6].to_string() == "50 GBP");
+        let table = query("JOURNAL 'Bank' FROM OPEN ON 2023-02-01");
+        assert!(table.rows.len() == 4);
+        let narration = "Opening balance for 'Assets:Bank' (Summarization)";
+        assert!(table.rows[0][3] == Value::Str(narration.to_string()));
+        assert!(table.rows[3][6].to_string() == "250 GBP");
+    }
+
+    #[test]
+    fn test_run_named() {
+        let table = run_named(&ledger(), "food").unwrap();
+        assert!(table.rows.len() == 2);
+        assert!(run_named(&ledger(), "nope").is_err());
+    }
+
+    #[test]
+    fn test_csv_json() {
+        let table = Table {
+            columns: vec!["name".to_string(), "total".to_string(), "tags".to_string()],
+            rows: vec![vec![
+                Value::Str("Say \"hi\", ok".to_string()),
+                Value::Number(Decimal::new(1050, 2)),
+                Value::List(vec![Value::Str("a".to_string()), Value::Null]),
+            ]],
+        };
+        assert!(table.to_csv() == "name,total,tags\n\"Say \"\"hi\"\", ok\",10.50,\"a,\"\n");
+        let want = "[\n  {\"name\": \"Say \\\"hi\\\", ok\", \"total\": 10.50, \"tags\": [\"a\", null]}\n]\n";
+        assert!(table.to_json() == want);
+        assert!(Table::default().to_json() == "[]\n");
+    }
+
+    #[test]
+    fn test_render() {
+        let table =
+            query("SELECT account, sum(number) AS total GROUP BY account ORDER BY total LIMIT 2");
+        let want = "\
+account        total
+Income:Job     -1000
+Assets:Invest      2
+";
+        assert_eq!(table.render(), want);
+    }
+}
@@ -0,0 +1,268 @@
+//! The account tree, like beancount's `realization`
+//!
+//! Balances are calculated per (leaf) account, and this arranges them into
+//! a tree where each node has its own balance and the total of its children.
+
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::data::{AccBal, Account, CcyBal, Options};
+
+/// How to render the account tree
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    /// Only show accounts this many levels deep, eg 1 for just the roots
+    pub depth: Option<usize>,
+    /// Don't show accounts whose total is zero in every currency
+    pub hide_zero: bool,
+}
+
+/// A node in the account tree
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RealAccount {
+    /// The full account name, empty for the root of the tree
+    pub account: Account,
+    /// The balance of postings made directly to this account
+    pub balance: CcyBal,
+    /// The child accounts, keyed by the last component of their name
+    pub children: BTreeMap<String, RealAccount>,
+}
+
+impl RealAccount {
+    /// Build the tree from the balances of each account
+    pub fn realize(bals: &AccBal) -> Self {
+        let mut root = RealAccount::default();
+        for (account, ccy_bals) in bals {
+            let node = root.get_or_create(account);
+            for (ccy, number) in ccy_bals {
+                *node.balance.entry(ccy.clone()).or_default() += number;
+            }
+        }
+        root
+    }
+
+    fn get_or_create(&mut self, account: &str) -> &mut RealAccount {
+        let mut node = self;
+        for name in account.split(':') {
+            let parent = node.account.clone();
+            node = node
+                .children
+                .entry(name.to_string())
+                .or_insert_with(|| RealAccount {
+                    account: match parent.is_empty() {
+                        true => name.to_string(),
+                        false => format!("{parent}:{name}"),
+                    },
+                    ..Default::default()
+                });
+        }
+        node
+    }
+
+    /// The node for `account`, if it is in the tree
+    pub fn get(&self, account: &str) -> Option<&RealAccount> {
+        let mut node = self;
+        for name in account.split(':') {
+            node = node.children.get(name)?;
+        }
+        Some(node)
+    }
+
+    /// The last component of the account name
+    pub fn name(&self) -> &str {
+        self.account.rsplit(':').next().unwrap_or_default()
+    }
+
+    /// The balance of this account plus that of all its children
+    pub fn total(&self) -> CcyBal {
+        let mut total = self.balance.clone();
+        for child in self.children.values() {
+            for (ccy, number) in child.total() {
+                *total.entry(ccy).or_default() += number;
+            }
+        }
+        total
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.total().values().all(Decimal::is_zero)
+    }
+
+    /// Render the tree as indented rows of account, own balance and total,
+    /// with a row for each currency
+    /// Roots and currencies are ordered as set out by `opts`
+    pub fn render(&self, tree: &TreeOptions, opts: &Options) -> String {
+        let mut rows = vec![header()];
+        rows.extend(self.rows(tree, opts));
+        format_table(&rows)
+    }
+
+    /// The rows of the rendered tree, without the header
+    pub fn rows(&self, tree: &TreeOptions, opts: &Options) -> Vec<[String; 3]> {
+        let mut rows: Vec<[String; 3]> = Vec::new();
+        let mut roots: Vec<&RealAccount> = self.children.values().collect();
+        roots.sort_by(|a, b| {
+            opts.account_order(&a.account)
+                .cmp(&opts.account_order(&b.account))
+        });
+        for child in roots {
+            child.render_rows(0, tree, opts, &mut rows);
+        }
+        rows
+    }
+
+    fn render_rows(
+        &self,
+        level: usize,
+        tree: &TreeOptions,
+        opts: &Options,
+        rows: &mut Vec<[String; 3]>,
+    ) {
+        if tree.depth.is_some_and(|d| level >= d) || tree.hide_zero && self.is_zero() {
+            return;
+        }
+        let total = self.total();
+        let total = opts.sorted_ccys(&total);
+        let indent = "  ".repeat(level);
+        let mut name = format!("{indent}{name}", name = self.name());
+        if total.is_empty() {
+            rows.push([std::mem::take(&mut name), String::new(), String::new()]);
+        }
+        for (ccy, number) in total {
+            let balance = match self.balance.get(ccy) {
+                Some(own) => format!("{own} {ccy}"),
+                None => String::new(),
+            };
+            rows.push([
+                std::mem::take(&mut name),
+                balance,
+                format!("{number} {ccy}"),
+            ]);
+        }
+        for child in self.children.values() {
+            child.render_rows(level + 1, tree, opts, rows);
+        }
+    }
+}
+
+/// The header row for a table of account, own balance and total
+pub fn header() -> [String; 3] {
+    ["Account", "Balance", "Total"].map(String::from)
+}
+
+/// Rows for a labelled total that isn't an account, eg "Net Income"
+pub fn total_rows(label: &str, total: &CcyBal, opts: &Options) -> Vec<[String; 3]> {
+    let mut label = label.to_string();
+    let mut rows: Vec<[String; 3]> = Vec::new();
+    for (ccy, number) in opts.sorted_ccys(total) {
+        rows.push([
+            std::mem::take(&mut label),
+            String::new(),
+            format!("{number} {ccy}"),
+        ]);
+    }
+    if rows.is_empty() {
+        rows.push([label, String::new(), "0".to_string()]);
+    }
+    rows
+}
+
+/// Align the rows into columns, with the amounts right-aligned
+pub fn format_table(rows: &[[String; 3]]) -> String {
+    format_columns(rows, 1)
+}
+
+/// Align the rows into columns, where the first `left` columns
+/// are left-aligned and the rest are right-aligned
+pub fn format_columns<R: AsRef<[String]>>(rows: &[R], left: usize) -> String {
+    let n = rows.first().map_or(0, |r| r.as_ref().len());
+    let right: Vec<bool> = (0..n).map(|i| i >= left).collect();
+    format_aligned(rows, &right)
+}
+
+/// Align the rows into columns, right-aligning the columns marked in `right`
+pub fn format_aligned<R: AsRef<[String]>>(rows: &[R], right: &[bool]) -> String {
+    let widths: Vec<usize> = (0..right.len())
+        .map(|i| {
+            rows.iter()
+                .map(|r| r.as_ref()[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .as_ref()
+            .iter()
+            .zip(&widths)
+            .zip(right)
+            .map(|((cell, &w), right)| match right {
+                false => format!("{cell:<w$}"),
+                true => format!("{cell:>w$}"),
+            })
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bals() -> AccBal {
+        let mut bals: AccBal = BTreeMap::new();
+        let mut add = |account: &str, number: i64, ccy: &str| {
+            let entry = bals.entry(account.to_string()).or_default();
+            entry.insert(ccy.to_string(), Decimal::new(number, 0));
+        };
+        add("Assets:Bank", 100, "GBP");
+        add("Assets:Bank:Savings", 50, "GBP");
+        add("Assets:Broker", 10, "USD");
+        add("Expenses:Food", 0, "GBP");
+        add("Income:Job", -150, "GBP");
+        add("Equity:Opening", 0, "GBP");
+        bals
+    }
+
+    #[test]
+    fn test_realize() {
+        let root = RealAccount::realize(&bals());
+        let assets = root.get("Assets").unwrap();
+        assert!(assets.balance.is_empty());
+        assert!(assets.total()["GBP"] == Decimal::new(150, 0));
+        assert!(assets.total()["USD"] == Decimal::new(10, 0));
+        let bank = root.get("Assets:Bank").unwrap();
+        assert!(bank.name() == "Bank");
+        assert!(bank.balance["GBP"] == Decimal::new(100, 0));
+        assert!(bank.total()["GBP"] == Decimal::new(150, 0));
+        assert!(root.get("Assets:Cash").is_none());
+        assert!(root.get("Expenses").unwrap().is_zero());
+    }
+
+    #[test]
+    fn test_render() {
+        let root = RealAccount::realize(&bals());
+        let tree = TreeOptions {
+            depth: Some(2),
+            hide_zero: true,
+        };
+        let opts = Options {
+            operating_currency: "USD".to_string(),
+            ..Default::default()
+        };
+        let want = "\
+Account    Balance     Total
+Assets                10 USD
+                     150 GBP
+  Bank     100 GBP   150 GBP
+  Broker    10 USD    10 USD
+Income              -150 GBP
+  Job     -150 GBP  -150 GBP
+";
+        assert_eq!(root.render(&tree, &opts), want);
+    }
+}
@@ -0,0 +1,1132 @@
+//! Financial statements, built on the balances from `book::get_balances`
+//!
+//! Balances are shown with their natural sign (see `AccountType::sign`),
+//! so income and liabilities are positive.
+
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::data::{
+    AccBal, Account, AccountType, CcyBal, DebugLine, Directive, Options, Posting, Transaction,
+};
+use crate::error::{BeanError, ErrorType};
+use crate::inventory::weight;
+use crate::prices::PriceMap;
+use crate::query::{Table, Value};
+use crate::realization::{self, RealAccount, TreeOptions};
+
+/// Add `sign` times each amount in `bal` to `into`
+fn add_scaled(into: &mut CcyBal, bal: &CcyBal, sign: i64) {
+    for (ccy, number) in bal {
+        *into.entry(ccy.clone()).or_default() += number * Decimal::from(sign);
+    }
+}
+
+/// The accounts of one type, with their balances multiplied by `AccountType::sign`
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub ty: AccountType,
+    pub tree: RealAccount,
+    pub total: CcyBal,
+}
+
+impl Group {
+    pub fn new(ty: AccountType, bals: &AccBal, opts: &Options) -> Self {
+        let mut signed: AccBal = AccBal::new();
+        for (account, ccy_bal) in bals {
+            if opts.account_type(account) == Some(ty) {
+                let bal = ccy_bal
+                    .iter()
+                    .map(|(ccy, number)| (ccy.clone(), number * ty.sign()))
+                    .collect();
+                signed.insert(account.clone(), bal);
+            }
+        }
+        let tree = RealAccount::realize(&signed);
+        let total = tree.total();
+        Self { ty, tree, total }
+    }
+
+    fn rows(&self, tree: &TreeOptions, opts: &Options) -> Vec<[String; 3]> {
+        let rows = self.tree.rows(tree, opts);
+        if rows.is_empty() {
+            return realization::total_rows(opts.account_root(self.ty), &self.total, opts);
+        }
+        rows
+    }
+}
+
+/// Income less expenses, which is positive for a profit
+fn net_income(bals: &AccBal, opts: &Options) -> CcyBal {
+    let mut net = CcyBal::new();
+    for (account, ccy_bal) in bals {
+        if opts
+            .account_type(account)
+            .is_some_and(|ty| ty.is_income_statement())
+        {
+            add_scaled(&mut net, ccy_bal, -1);
+        }
+    }
+    net
+}
+
+#[derive(Clone, Debug)]
+pub struct BalanceSheet {
+    /// Assets, Liabilities and Equity
+    pub groups: Vec<Group>,
+    /// Net income that hasn't been moved into an Equity account
+    pub retained_earnings: CcyBal,
+    /// Liabilities, Equity and retained earnings, which should equal Assets
+    pub liabilities_and_equity: CcyBal,
+}
+
+impl BalanceSheet {
+    pub fn new(bals: &AccBal, opts: &Options) -> Self {
+        let groups: Vec<Group> = AccountType::ALL
+            .into_iter()
+            .filter(AccountType::is_balance_sheet)
+            .map(|ty| Group::new(ty, bals, opts))
+            .collect();
+        let retained_earnings = net_income(bals, opts);
+        let mut liabilities_and_equity = retained_earnings.clone();
+        for group in groups.iter().filter(|g| g.ty != AccountType::Assets) {
+            add_scaled(&mut liabilities_and_equity, &group.total, 1);
+        }
+        Self {
+            groups,
+            retained_earnings,
+            liabilities_and_equity,
+        }
+    }
+
+    pub fn render(&self, tree: &TreeOptions, opts: &Options) -> String {
+        let mut rows = vec![realization::header()];
+        for group in &self.groups {
+            rows.extend(group.rows(tree, opts));
+        }
+        rows.push(Default::default());
+        rows.extend(realization::total_rows(
+            "Retained Earnings",
+            &self.retained_earnings,
+            opts,
+        ));
+        rows.extend(realization::total_rows(
+            "Total Liabilities and Equity",
+            &self.liabilities_and_equity,
+            opts,
+        ));
+        realization::format_table(&rows)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IncomeStatement {
+    /// Income and Expenses
+    pub groups: Vec<Group>,
+    /// Income less Expenses
+    pub net_income: CcyBal,
+}
+
+impl IncomeStatement {
+    pub fn new(bals: &AccBal, opts: &Options) -> Self {
+        let groups: Vec<Group> = AccountType::ALL
+            .into_iter()
+            .filter(AccountType::is_income_statement)
+            .map(|ty| Group::new(ty, bals, opts))
+            .collect();
+        let net_income = net_income(bals, opts);
+        Self { groups, net_income }
+    }
+
+    pub fn render(&self, tree: &TreeOptions, opts: &Options) -> String {
+        let mut rows = vec![realization::header()];
+        for group in &self.groups {
+            rows.extend(group.rows(tree, opts));
+        }
+        rows.push(Default::default());
+        rows.extend(realization::total_rows(
+            "Net Income",
+            &self.net_income,
+            opts,
+        ));
+        realization::format_table(&rows)
+    }
+}
+
+/// Every account's balance split into debit and credit columns,
+/// where the totals of each column should cancel out
+#[derive(Clone, Debug)]
+pub struct TrialBalance {
+    /// The non-zero balance of each account in each currency, in hierarchical order
+    pub rows: Vec<(Account, String, Decimal)>,
+    /// The sum of all the positive balances
+    pub debits: CcyBal,
+    /// The sum of all the negative balances, as positive numbers
+    pub credits: CcyBal,
+}
+
+impl TrialBalance {
+    pub fn new(bals: &AccBal, opts: &Options) -> Self {
+        let mut rows: Vec<(Account, String, Decimal)> = Vec::new();
+        let mut debits = CcyBal::new();
+        let mut credits = CcyBal::new();
+        for account in opts.sorted_accounts(bals) {
+            for (ccy, number) in opts.sorted_ccys(&bals[account]) {
+                if number.is_zero() {
+                    continue;
+                }
+                rows.push((account.clone(), ccy.clone(), *number));
+                match number.is_sign_positive() {
+                    true => *debits.entry(ccy.clone()).or_default() += number,
+                    false => *credits.entry(ccy.clone()).or_default() -= number,
+                }
+            }
+        }
+        Self {
+            rows,
+            debits,
+            credits,
+        }
+    }
+
+    /// An error for each currency whose debits and credits don't cancel out
+    pub fn check(&self) -> Vec<BeanError> {
+        let mut ccys: Vec<&String> = self.debits.keys().chain(self.credits.keys()).collect();
+        ccys.sort();
+        ccys.dedup();
+        let mut errs: Vec<BeanError> = Vec::new();
+        for ccy in ccys {
+            let debit = self.debits.get(ccy).copied().unwrap_or_default();
+            let credit = self.credits.get(ccy).copied().unwrap_or_default();
+            let diff = debit - credit;
+            // TODO get precision from context
+            if diff.abs() > Decimal::new(1, 3) {
+                let err = BeanError::new(
+                    ErrorType::UnbalancedTrial,
+                    &DebugLine::default(),
+                    &format!("Trial balance doesn't sum to zero: {diff} {ccy}"),
+                    None,
+                );
+                errs.push(err);
+            }
+        }
+        errs
+    }
+
+    pub fn render(&self, opts: &Options) -> String {
+        let mut rows = vec![["Account", "Debit", "Credit"].map(String::from)];
+        for (account, ccy, number) in &self.rows {
+            let amount = format!("{abs} {ccy}", abs = number.abs());
+            match number.is_sign_positive() {
+                true => rows.push([account.clone(), amount, String::new()]),
+                false => rows.push([account.clone(), String::new(), amount]),
+            }
+        }
+        rows.push(Default::default());
+        let mut label = "Total".to_string();
+        let mut totals: CcyBal = self.debits.clone();
+        totals.extend(self.credits.keys().map(|c| (c.clone(), Decimal::ZERO)));
+        for (ccy, _) in opts.sorted_ccys(&totals) {
+            let fmt =
+                |bal: &CcyBal| format!("{n} {ccy}", n = bal.get(ccy).copied().unwrap_or_default());
+            rows.push([
+                std::mem::take(&mut label),
+                fmt(&self.debits),
+                fmt(&self.credits),
+            ]);
+        }
+        realization::format_table(&rows)
+    }
+}
+
+/// What a cash flow is for, from the account on the other side of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlowCategory {
+    Operating,
+    Investing,
+    Financing,
+}
+
+impl FlowCategory {
+    pub const ALL: [FlowCategory; 3] = [
+        FlowCategory::Operating,
+        FlowCategory::Investing,
+        FlowCategory::Financing,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            FlowCategory::Operating => "Operating",
+            FlowCategory::Investing => "Investing",
+            FlowCategory::Financing => "Financing",
+        }
+    }
+
+    /// By default flows to Income and Expenses are operating,
+    /// to other Assets are investing, and to Liabilities and Equity are financing
+    fn default_for(ty: Option<AccountType>) -> Self {
+        match ty {
+            Some(AccountType::Assets) => FlowCategory::Investing,
+            Some(AccountType::Liabilities | AccountType::Equity) => FlowCategory::Financing,
+            _ => FlowCategory::Operating,
+        }
+    }
+}
+
+/// The `cashflow` metadata on the Open of each account that has it
+fn cashflow_meta(dirs: &[Directive]) -> BTreeMap<Account, String> {
+    let mut meta: BTreeMap<Account, String> = BTreeMap::new();
+    for d in dirs {
+        if let Directive::Open(open) = d {
+            if let Some(m) = open.meta.iter().find(|m| m.key == "cashflow") {
+                meta.insert(open.account.clone(), m.val.as_str().to_lowercase());
+            }
+        }
+    }
+    meta
+}
+
+/// The metadata of the account, or of its closest parent that has some
+fn inherited<'a>(meta: &'a BTreeMap<Account, String>, account: &str) -> Option<&'a str> {
+    let mut account = account;
+    loop {
+        if let Some(val) = meta.get(account) {
+            return Some(val);
+        }
+        account = &account[..account.rfind(':')?];
+    }
+}
+
+/// The movements of cash, split by what they were for, over a period
+///
+/// Cash accounts are those opened with `cashflow: "cash"` metadata (which
+/// subaccounts inherit) or matching a regex. Each Transaction that touches cash
+/// is attributed to its other postings, categorised by `FlowCategory::default_for`
+/// unless their Open has `cashflow: "operating"`, `"investing"` or `"financing"`.
+#[derive(Clone, Debug)]
+pub struct CashFlow {
+    /// Cash balances before the period
+    pub opening: CcyBal,
+    /// The flows into cash (positive) or out of it (negative) by the other account
+    pub sections: Vec<(FlowCategory, AccBal)>,
+    /// Cash balances at the end of the period
+    pub closing: CcyBal,
+}
+
+impl CashFlow {
+    /// Transactions before `begin` only count towards the opening balances
+    pub fn new(
+        dirs: &[Directive],
+        begin: Option<NaiveDate>,
+        cash: Option<&Regex>,
+        opts: &Options,
+    ) -> Self {
+        let meta = cashflow_meta(dirs);
+        let is_cash = |account: &str| {
+            inherited(&meta, account) == Some("cash") || cash.is_some_and(|re| re.is_match(account))
+        };
+        let category = |account: &str| match inherited(&meta, account) {
+            Some("operating") => FlowCategory::Operating,
+            Some("investing") => FlowCategory::Investing,
+            Some("financing") => FlowCategory::Financing,
+            _ => FlowCategory::default_for(opts.account_type(account)),
+        };
+
+        let mut opening = CcyBal::new();
+        let mut closing = CcyBal::new();
+        let mut sections: Vec<(FlowCategory, AccBal)> = FlowCategory::ALL
+            .into_iter()
+            .map(|c| (c, AccBal::new()))
+            .collect();
+        for d in dirs {
+            let Directive::Transaction(tx) = d else {
+                continue;
+            };
+            let (cash, others): (Vec<&Posting>, Vec<&Posting>) =
+                tx.postings.iter().partition(|p| is_cash(&p.account));
+            if cash.is_empty() {
+                continue;
+            }
+            let before = begin.is_some_and(|begin| tx.date < begin);
+            for amount in cash.iter().filter_map(|p| p.amount.as_ref()) {
+                *closing.entry(amount.ccy.clone()).or_default() += amount.number;
+                if before {
+                    *opening.entry(amount.ccy.clone()).or_default() += amount.number;
+                }
+            }
+            if before {
+                continue;
+            }
+            for p in others {
+                let Some(weight) = weight(p) else {
+                    continue;
+                };
+                let (_, flows) = &mut sections[category(&p.account) as usize];
+                *flows
+                    .entry(p.account.clone())
+                    .or_default()
+                    .entry(weight.ccy)
+                    .or_default() -= weight.number;
+            }
+        }
+        Self {
+            opening,
+            sections,
+            closing,
+        }
+    }
+
+    /// The sum of the flows in one category
+    pub fn total(&self, category: FlowCategory) -> CcyBal {
+        let mut total = CcyBal::new();
+        let flows = self.sections.iter().filter(|(c, _)| *c == category);
+        for bal in flows.flat_map(|(_, flows)| flows.values()) {
+            add_scaled(&mut total, bal, 1);
+        }
+        total
+    }
+
+    /// The change in cash over the period
+    pub fn net(&self) -> CcyBal {
+        let mut net = CcyBal::new();
+        for category in FlowCategory::ALL {
+            add_scaled(&mut net, &self.total(category), 1);
+        }
+        net
+    }
+
+    pub fn render(&self, tree: &TreeOptions, opts: &Options) -> String {
+        let mut rows = vec![["Account", "Flow", "Total"].map(String::from)];
+        rows.extend(realization::total_rows("Opening Cash", &self.opening, opts));
+        for (category, flows) in &self.sections {
+            rows.push(Default::default());
+            rows.push([
+                format!("{} Activities", category.name()),
+                String::new(),
+                String::new(),
+            ]);
+            rows.extend(RealAccount::realize(flows).rows(tree, opts));
+            let label = format!("Net Cash from {}", category.name());
+            rows.extend(realization::total_rows(
+                &label,
+                &self.total(*category),
+                opts,
+            ));
+        }
+        rows.push(Default::default());
+        rows.extend(realization::total_rows(
+            "Net Change in Cash",
+            &self.net(),
+            opts,
+        ));
+        rows.extend(realization::total_rows("Closing Cash", &self.closing, opts));
+        realization::format_table(&rows)
+    }
+}
+
+/// One matching posting in an account register
+#[derive(Clone, Debug)]
+pub struct RegisterRow {
+    pub tx: Transaction,
+    /// The index of the matching posting in `tx.postings`
+    pub index: usize,
+    /// The running balance of all matching accounts, after this posting
+    pub balance: CcyBal,
+}
+
+impl RegisterRow {
+    pub fn posting(&self) -> &Posting {
+        &self.tx.postings[self.index]
+    }
+
+    /// The other legs of the Transaction
+    pub fn others(&self) -> impl Iterator<Item = &Posting> {
+        let index = self.index;
+        self.tx
+            .postings
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| *i != index)
+            .map(|(_, p)| p)
+    }
+}
+
+/// Match whole account names against `pattern`,
+/// and also their subaccounts if `subaccounts` is set
+pub fn account_regex(pattern: &str, subaccounts: bool) -> Result<Regex, regex::Error> {
+    let children = match subaccounts {
+        true => "(:.+)?",
+        false => "",
+    };
+    Regex::new(&format!("^(?:{pattern}){children}$"))
+}
+
+/// Every posting to an account matching `re`, with the running balance
+pub fn register(dirs: &[Directive], re: &Regex) -> Vec<RegisterRow> {
+    let mut rows: Vec<RegisterRow> = Vec::new();
+    let mut balance = CcyBal::new();
+    for d in dirs {
+        let Directive::Transaction(tx) = d else {
+            continue;
+        };
+        for (index, p) in tx.postings.iter().enumerate() {
+            if !re.is_match(&p.account) {
+                continue;
+            }
+            if let Some(amount) = &p.amount {
+                *balance.entry(amount.ccy.clone()).or_default() += amount.number;
+            }
+            rows.push(RegisterRow {
+                tx: tx.clone(),
+                index,
+                balance: balance.clone(),
+            });
+        }
+    }
+    rows
+}
+
+/// Render the register with a line per posting, and more lines for extra
+/// currencies in the balance and the other legs if `others` is set
+pub fn render_register(rows: &[RegisterRow], others: bool, opts: &Options) -> String {
+    let header = ["Date", "Description", "Account", "Amount", "Balance"];
+    let mut table: Vec<[String; 5]> = vec![header.map(String::from)];
+    let amount = |p: &Posting| p.amount.as_ref().map(|a| a.to_string()).unwrap_or_default();
+    for row in rows {
+        let tx = &row.tx;
+        let description = match &tx.payee {
+            Some(payee) => format!("{payee} | {narration}", narration = tx.narration),
+            None => tx.narration.clone(),
+        };
+        let mut first = [
+            format!("{date} {ty}", date = tx.date, ty = tx.ty),
+            description,
+            row.posting().account.clone(),
+            amount(row.posting()),
+        ];
+        for (ccy, number) in opts.sorted_ccys(&row.balance) {
+            let [date, desc, account, amount] = std::mem::take(&mut first);
+            table.push([date, desc, account, amount, format!("{number} {ccy}")]);
+        }
+        if others {
+            for p in row.others() {
+                let account = format!("  {account}", account = p.account);
+                table.push([
+                    String::new(),
+                    String::new(),
+                    account,
+                    amount(p),
+                    String::new(),
+                ]);
+            }
+        }
+    }
+    realization::format_columns(&table, 3)
+}
+
+/// How long each column of a periodic report, or step of a time series, covers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl std::str::FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" | "daily" => Ok(Interval::Day),
+            "week" | "weekly" => Ok(Interval::Week),
+            "month" | "monthly" => Ok(Interval::Month),
+            "quarter" | "quarterly" => Ok(Interval::Quarter),
+            "year" | "yearly" => Ok(Interval::Year),
+            _ => Err(format!(
+                "Unknown interval '{s}', use day, week, month, quarter or year"
+            )),
+        }
+    }
+}
+
+impl Interval {
+    /// The first day of the period that `date` is in, weeks starting on Monday
+    pub fn start(&self, date: NaiveDate) -> NaiveDate {
+        let months = match self {
+            Interval::Day => return date,
+            Interval::Week => return date.week(Weekday::Mon).first_day(),
+            Interval::Month => 1,
+            Interval::Quarter => 3,
+            Interval::Year => 12,
+        };
+        let month0 = date.month0() - date.month0() % months;
+        NaiveDate::from_ymd_opt(date.year(), month0 + 1, 1).unwrap_or(date)
+    }
+
+    /// The first day of the period after the one starting on `start`
+    fn next(&self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Interval::Day => start.succ_opt(),
+            Interval::Week => start.checked_add_days(Days::new(7)),
+            Interval::Month => start.checked_add_months(Months::new(1)),
+            Interval::Quarter => start.checked_add_months(Months::new(3)),
+            Interval::Year => start.checked_add_months(Months::new(12)),
+        }
+    }
+
+    /// The name of the period starting on `start`, eg 2023-01-02, 2023-W01, 2023-01, 2023Q1 or 2023
+    pub fn label(&self, start: NaiveDate) -> String {
+        match self {
+            Interval::Day => start.to_string(),
+            Interval::Week => start.format("%G-W%V").to_string(),
+            Interval::Month => start.format("%Y-%m").to_string(),
+            Interval::Quarter => format!("{}Q{}", start.year(), start.month0() / 3 + 1),
+            Interval::Year => start.year().to_string(),
+        }
+    }
+
+    /// The periods, each from its start (inclusive) to the next one's (exclusive),
+    /// that cover `begin` (inclusive) to `end` (exclusive)
+    pub fn periods(&self, begin: NaiveDate, end: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+        let mut periods = Vec::new();
+        let mut start = self.start(begin);
+        while start < end {
+            let Some(next) = self.next(start) else {
+                break;
+            };
+            periods.push((start, next));
+            start = next;
+        }
+        periods
+    }
+}
+
+/// Balances per account and currency in a column for each period
+#[derive(Clone, Debug)]
+pub struct PeriodicReport {
+    pub interval: Interval,
+    pub periods: Vec<(NaiveDate, NaiveDate)>,
+    /// Whether the values are balances at the end of each period,
+    /// rather than the changes during it
+    pub cumulative: bool,
+    /// The account, currency and value for each period, in account order,
+    /// with the natural sign of the account's type
+    pub rows: Vec<(Account, String, Vec<Decimal>)>,
+}
+
+impl PeriodicReport {
+    /// Build the report from the Transactions in `dirs` for `begin` (inclusive)
+    /// to `end` (exclusive), by default from the first Transaction to the last
+    /// Accounts deeper than `tree.depth` are combined into their parents
+    pub fn new(
+        dirs: &[Directive],
+        interval: Interval,
+        begin: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+        cumulative: bool,
+        tree: &TreeOptions,
+        opts: &Options,
+    ) -> Self {
+        let txs: Vec<&Transaction> = dirs
+            .iter()
+            .filter_map(|d| match d {
+                Directive::Transaction(tx) => Some(tx),
+                _ => None,
+            })
+            .collect();
+        let first = txs.iter().map(|tx| tx.date).min();
+        let last = txs.iter().filter_map(|tx| tx.date.succ_opt()).max();
+        let periods = match (begin.or(first), end.or(last)) {
+            (Some(begin), Some(end)) => interval.periods(begin, end),
+            _ => Vec::new(),
+        };
+
+        // The changes in each period, and before the first for cumulative balances
+        let mut values: BTreeMap<(Account, String), (Decimal, Vec<Decimal>)> = BTreeMap::new();
+        for tx in txs {
+            let before = periods.first().is_none_or(|(start, _)| tx.date < *start);
+            let i = periods
+                .iter()
+                .position(|(start, end)| *start <= tx.date && tx.date < *end);
+            if i.is_none() && !(cumulative && before) {
+                continue;
+            }
+            for p in &tx.postings {
+                let Some(amount) = &p.amount else {
+                    continue;
+                };
+                let account = match tree.depth {
+                    Some(depth) => p
+                        .account
+                        .split(':')
+                        .take(depth)
+                        .collect::<Vec<_>>()
+                        .join(":"),
+                    None => p.account.clone(),
+                };
+                let sign = opts
+                    .account_type(&account)
+                    .map_or(Decimal::ONE, |ty| ty.sign());
+                let (opening, changes) = values
+                    .entry((account, amount.ccy.clone()))
+                    .or_insert_with(|| (Decimal::ZERO, vec![Decimal::ZERO; periods.len()]));
+                match i {
+                    Some(i) => changes[i] += amount.number * sign,
+                    None => *opening += amount.number * sign,
+                }
+            }
+        }
+
+        let mut rows: Vec<(Account, String, Vec<Decimal>)> = Vec::new();
+        for ((account, ccy), (opening, changes)) in values {
+            let values = match cumulative {
+                true => changes
+                    .iter()
+                    .scan(opening, |bal, change| {
+                        *bal += change;
+                        Some(*bal)
+                    })
+                    .collect(),
+                false => changes,
+            };
+            if tree.hide_zero && values.iter().all(Decimal::is_zero) {
+                continue;
+            }
+            rows.push((account, ccy, values));
+        }
+        rows.sort_by(|(a, a_ccy, _), (b, b_ccy, _)| {
+            (opts.account_order(a), opts.ccy_order(a_ccy))
+                .cmp(&(opts.account_order(b), opts.ccy_order(b_ccy)))
+        });
+        Self {
+            interval,
+            periods,
+            cumulative,
+            rows,
+        }
+    }
+
+    /// The total of a row's changes, or its final balance if cumulative
+    pub fn total(&self, values: &[Decimal]) -> Decimal {
+        match self.cumulative {
+            true => values.last().copied().unwrap_or_default(),
+            false => values.iter().sum(),
+        }
+    }
+
+    /// The average value per period, rounded to two decimal places
+    /// or as many as the values have
+    pub fn average(&self, values: &[Decimal]) -> Decimal {
+        if values.is_empty() {
+            return Decimal::ZERO;
+        }
+        let scale = values.iter().map(Decimal::scale).max().unwrap_or(0).max(2);
+        let sum: Decimal = values.iter().sum();
+        (sum / Decimal::from(values.len())).round_dp(scale)
+    }
+
+    /// The report as a Table, with Total and Average columns
+    pub fn table(&self) -> Table {
+        let mut columns = vec!["Account".to_string(), "Currency".to_string()];
+        columns.extend(
+            self.periods
+                .iter()
+                .map(|(start, _)| self.interval.label(*start)),
+        );
+        columns.extend(["Total".to_string(), "Average".to_string()]);
+        let rows = self
+            .rows
+            .iter()
+            .map(|(account, ccy, values)| {
+                let mut row = vec![Value::Str(account.clone()), Value::Str(ccy.clone())];
+                row.extend(values.iter().map(|v| Value::Number(*v)));
+                row.push(Value::Number(self.total(values)));
+                row.push(Value::Number(self.average(values)));
+                row
+            })
+            .collect();
+        Table { columns, rows }
+    }
+}
+
+/// The balances of some accounts at the end of each period, for charting
+#[derive(Clone, Debug)]
+pub struct TimeSeries {
+    /// The last day of each period
+    pub dates: Vec<NaiveDate>,
+    /// The name of each series and its balance on each date
+    pub series: Vec<(String, Vec<CcyBal>)>,
+}
+
+impl TimeSeries {
+    /// Net worth (Assets and Liabilities), then the total of the accounts matching
+    /// each regex, from `begin` to `end` (exclusive), by default from the first
+    /// Transaction to the last
+    pub fn new(
+        dirs: &[Directive],
+        interval: Interval,
+        begin: Option<NaiveDate>,
+        end: Option<NaiveDate>,
+        accounts: &[(String, Regex)],
+        opts: &Options,
+    ) -> Self {
+        let txs: Vec<&Transaction> = dirs
+            .iter()
+            .filter_map(|d| match d {
+                Directive::Transaction(tx) => Some(tx),
+                _ => None,
+            })
+            .collect();
+        let first = txs.iter().map(|tx| tx.date).min();
+        let last = txs.iter().filter_map(|tx| tx.date.succ_opt()).max();
+        let periods = match (begin.or(first), end.or(last)) {
+            (Some(begin), Some(end)) => interval.periods(begin, end),
+            _ => Vec::new(),
+        };
+
+        let net_worth = |account: &str| {
+            matches!(
+                opts.account_type(account),
+                Some(AccountType::Assets | AccountType::Liabilities)
+            )
+        };
+        let mut series: Vec<(String, Vec<CcyBal>)> = vec![("Net Worth".to_string(), Vec::new())];
+        series.extend(accounts.iter().map(|(name, _)| (name.clone(), Vec::new())));
+        let mut balances = vec![CcyBal::new(); series.len()];
+        let mut txs = txs.into_iter().peekable();
+        for (_, next) in &periods {
+            while let Some(tx) = txs.next_if(|tx| tx.date < *next) {
+                for p in &tx.postings {
+                    let Some(amount) = &p.amount else {
+                        continue;
+                    };
+                    let matched = std::iter::once(net_worth(&p.account))
+                        .chain(accounts.iter().map(|(_, re)| re.is_match(&p.account)));
+                    for (bal, matched) in balances.iter_mut().zip(matched) {
+                        if matched {
+                            *bal.entry(amount.ccy.clone()).or_default() += amount.number;
+                        }
+                    }
+                }
+            }
+            for ((_, values), bal) in series.iter_mut().zip(&balances) {
+                values.push(bal.clone());
+            }
+        }
+        let dates = periods
+            .iter()
+            .filter_map(|(_, next)| next.pred_opt())
+            .collect();
+        Self { dates, series }
+    }
+
+    /// Convert each balance into `ccy` at the prices on its date,
+    /// leaving amounts without a price in their own currency
+    pub fn convert(&mut self, prices: &PriceMap, ccy: &str) {
+        for (_, values) in &mut self.series {
+            for (bal, date) in values.iter_mut().zip(&self.dates) {
+                *bal = prices.convert_bal(bal, ccy, *date);
+            }
+        }
+    }
+
+    /// The series as a Table with a row per date,
+    /// and a column per series and currency, eg `Net Worth (GBP)`
+    pub fn table(&self, opts: &Options) -> Table {
+        let mut columns = vec!["Date".to_string()];
+        let mut keys: Vec<(usize, String)> = Vec::new();
+        for (i, (name, values)) in self.series.iter().enumerate() {
+            let mut ccys: Vec<&String> = values.iter().flat_map(|bal| bal.keys()).collect();
+            ccys.sort_by(|a, b| opts.ccy_order(a).cmp(&opts.ccy_order(b)));
+            ccys.dedup();
+            for ccy in ccys {
+                columns.push(format!("{name} ({ccy})"));
+                keys.push((i, ccy.clone()));
+            }
+        }
+        let rows = self
+            .dates
+            .iter()
+            .enumerate()
+            .map(|(d, date)| {
+                let mut row = vec![Value::Date(*date)];
+                for (i, ccy) in &keys {
+                    let number = self.series[*i].1[d].get(ccy).copied().unwrap_or_default();
+                    row.push(Value::Number(number));
+                }
+                row
+            })
+            .collect();
+        Table { columns, rows }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Amount;
+    use crate::{book, period};
+
+    const TEXT: &str = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Liabilities:Card
+2023-01-01 open Equity:Opening
+2023-01-01 open Income:Job
+2023-01-01 open Expenses:Food
+
+2023-01-01 * "Opening"
+  Assets:Bank      100 GBP
+  Equity:Opening
+
+2023-01-15 * "Salary"
+  Assets:Bank     1000 GBP
+  Income:Job
+
+2023-02-01 * "Dinner"
+  Expenses:Food     30 GBP
+  Liabilities:Card
+
+2023-02-15 * "Shop"
+  Expenses:Food     20 GBP
+  Assets:Bank
+"#;
+
+    fn gbp(n: i64) -> CcyBal {
+        CcyBal::from([("GBP".to_string(), Decimal::new(n, 0))])
+    }
+
+    #[test]
+    fn test_balance_sheet() {
+        let mut ledger = crate::load(TEXT.to_string());
+        let (bals, _) = book::get_balances(&mut ledger.dirs);
+        let bs = BalanceSheet::new(&bals, &ledger.opts);
+        let totals: Vec<&CcyBal> = bs.groups.iter().map(|g| &g.total).collect();
+        assert!(totals == [&gbp(1080), &gbp(30), &gbp(100)]);
+        assert!(bs.retained_earnings == gbp(950));
+        assert!(bs.liabilities_and_equity == bs.groups[0].total);
+
+        let out = bs.render(&TreeOptions::default(), &ledger.opts);
+        assert!(out.contains("Retained Earnings"));
+        assert!(!out.contains("Income"));
+    }
+
+    #[test]
+    fn test_income_statement() {
+        let mut ledger = crate::load(TEXT.to_string());
+        let (bals, _) = book::get_balances(&mut ledger.dirs);
+        let is = IncomeStatement::new(&bals, &ledger.opts);
+        assert!(is.groups[0].total == gbp(1000));
+        assert!(is.groups[1].total == gbp(50));
+        assert!(is.net_income == gbp(950));
+
+        // Only February
+        let begin = NaiveDate::from_ymd_opt(2023, 2, 1);
+        let end = NaiveDate::from_ymd_opt(2023, 3, 1);
+        let mut clamped = period::clamp(&ledger, begin, end);
+        let (bals, _) = book::get_balances(&mut clamped.dirs);
+        let is = IncomeStatement::new(&bals, &ledger.opts);
+        assert!(is.groups[0].total.values().all(Decimal::is_zero));
+        assert!(is.net_income == gbp(-50));
+    }
+
+    #[test]
+    fn test_trial_balance() {
+        let mut ledger = crate::load(TEXT.to_string());
+        let (bals, _) = book::get_balances(&mut ledger.dirs);
+        let tb = TrialBalance::new(&bals, &ledger.opts);
+        assert!(tb.rows.len() == 5);
+        assert!(tb.rows[0].0 == "Assets:Bank");
+        assert!(tb.debits == gbp(1130));
+        assert!(tb.credits == gbp(1130));
+        assert!(tb.check().is_empty());
+
+        let mut bals = bals;
+        bals.get_mut("Assets:Bank")
+            .unwrap()
+            .insert("USD".to_string(), Decimal::ONE);
+        let tb = TrialBalance::new(&bals, &ledger.opts);
+        let errs = tb.check();
+        assert!(errs.len() == 1);
+        assert!(errs[0].ty == ErrorType::UnbalancedTrial);
+        assert!(errs[0].msg.contains("1 USD"));
+        assert!(errs[0].to_string().starts_with("Trial balance"));
+
+        // Conversions between currencies are balanced by those added to the period
+        let text = r#"
+2023-01-01 open Assets:Bank
+2023-01-01 open Assets:Dollars
+
+2023-01-15 * "Exchange"
+  Assets:Dollars   100 USD @ 0.80 GBP
+  Assets:Bank     -80.00 GBP
+"#;
+        let ledger = crate::load(text.to_string());
+        let (bals, _) = book::get_balances(&mut ledger.dirs.clone());
+        assert!(!TrialBalance::new(&bals, &ledger.opts).check().is_empty());
+        let mut clamped = period::clamp(&ledger, None, None);
+        let (bals, errs) = book::get_balances(&mut clamped.dirs);
+        assert!(errs.is_empty());
+        let tb = TrialBalance::new(&bals, &ledger.opts);
+        assert!(tb.check().is_empty());
+        assert!(tb.rows.iter().any(|r| r.0 == "Equity:Conversions:Current"));
+    }
+
+    #[test]
+    fn test_cash_flow() {
+        let text = format!(
+            "{TEXT}
+2023-01-01 open Assets:Savings
+  cashflow: \"cash\"
+2023-01-01 open Expenses:Card-Fees
+  cashflow: \"financing\"
+
+2023-03-01 * \"Repay card\"
+  Liabilities:Card    30 GBP
+  Expenses:Card-Fees   1 GBP
+  Assets:Savings
+"
+        );
+        let ledger = crate::load(text);
+        let begin = NaiveDate::from_ymd_opt(2023, 1, 10);
+        let re = account_regex("Assets:Bank", true).unwrap();
+        let cf = CashFlow::new(&ledger.dirs, begin, Some(&re), &ledger.opts);
+        assert!(cf.opening == gbp(100));
+        assert!(cf.total(FlowCategory::Operating) == gbp(980));
+        assert!(cf.total(FlowCategory::Investing).is_empty());
+        assert!(cf.total(FlowCategory::Financing) == gbp(-31));
+        assert!(cf.sections[2].1["Expenses:Card-Fees"] == gbp(-1));
+        assert!(cf.net() == gbp(949));
+        assert!(cf.closing == gbp(1049));
+
+        let out = cf.render(&TreeOptions::default(), &ledger.opts);
+        assert!(out.contains("Net Cash from Operating"));
+        assert!(out.contains("Card-Fees"));
+
+        // Without the regex only the savings account is cash
+        let cf = CashFlow::new(&ledger.dirs, None, None, &ledger.opts);
+        assert!(cf.closing == gbp(-31));
+    }
+
+    #[test]
+    fn test_register() {
+        let ledger = crate::load(TEXT.to_string());
+        let re = account_regex("Assets:Bank", false).unwrap();
+        let rows = register(&ledger.dirs, &re);
+        let balances: Vec<&CcyBal> = rows.iter().map(|r| &r.balance).collect();
+        assert!(balances == [&gbp(100), &gbp(1100), &gbp(1080)]);
+        assert!(rows[2].posting().account == "Assets:Bank");
+        let others: Vec<&str> = rows[2].others().map(|p| p.account.as_str()).collect();
+        assert!(others == ["Expenses:Food"]);
+
+        let out = render_register(&rows, true, &ledger.opts);
+        assert!(out.lines().count() == 7);
+        assert!(out.contains("2023-02-15 *  Shop"));
+
+        assert!(register(&ledger.dirs, &account_regex("Assets", false).unwrap()).is_empty());
+        let re = account_regex("Assets|Liabilities", true).unwrap();
+        assert!(register(&ledger.dirs, &re).len() == 4);
+    }
+
+    #[test]
+    fn test_periodic_report() {
+        let ledger = crate::load(TEXT.to_string());
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let periods = Interval::Quarter.periods(d(2023, 2, 10), d(2023, 7, 1));
+        assert!(
+            periods
+                == [
+                    (d(2023, 1, 1), d(2023, 4, 1)),
+                    (d(2023, 4, 1), d(2023, 7, 1))
+                ]
+        );
+        assert!(Interval::Quarter.label(d(2023, 4, 1)) == "2023Q2");
+        assert!("yearly".parse::<Interval>() == Ok(Interval::Year));
+        assert!("fortnightly".parse::<Interval>().is_err());
+
+        let tree = TreeOptions::default();
+        let report = PeriodicReport::new(
+            &ledger.dirs,
+            Interval::Month,
+            None,
+            None,
+            false,
+            &tree,
+            &ledger.opts,
+        );
+        assert!(report.periods.len() == 2);
+        let bank = &report.rows[0];
+        assert!(bank.0 == "Assets:Bank");
+        assert!(bank.2 == [Decimal::new(1100, 0), Decimal::new(-20, 0)]);
+        assert!(report.total(&bank.2) == Decimal::new(1080, 0));
+        assert!(report.average(&bank.2) == Decimal::new(540, 0));
+
+        // Balances from March, with the earlier changes as opening balances
+        let tree = TreeOptions {
+            depth: Some(1),
+            hide_zero: true,
+        };
+        let report = PeriodicReport::new(
+            &ledger.dirs,
+            Interval::Month,
+            Some(d(2023, 3, 1)),
+            Some(d(2023, 5, 1)),
+            true,
+            &tree,
+            &ledger.opts,
+        );
+        let table = report.table();
+        assert!(table.columns[2..] == ["2023-03", "2023-04", "Total", "Average"]);
+        assert!(table.rows.len() == 5);
+        assert!(table.rows[3][0] == Value::Str("Income".into()));
+        assert!(table.rows[3][4] == Value::Number(Decimal::new(1000, 0)));
+    }
+
+    #[test]
+    fn test_time_series() {
+        let ledger = crate::load(TEXT.to_string());
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert!(Interval::Week.start(d(2023, 1, 1)) == d(2022, 12, 26));
+        assert!(Interval::Week.label(d(2023, 1, 2)) == "2023-W01");
+
+        let accounts = [(
+            "Expenses".to_string(),
+            account_regex("Expenses", true).unwrap(),
+        )];
+        let mut series = TimeSeries::new(
+            &ledger.dirs,
+            Interval::Month,
+            None,
+            Some(d(2023, 4, 1)),
+            &accounts,
+            &ledger.opts,
+        );
+        assert!(series.dates == [d(2023, 1, 31), d(2023, 2, 28), d(2023, 3, 31)]);
+        assert!(series.series[0].0 == "Net Worth");
+        assert!(series.series[0].1 == [gbp(1100), gbp(1050), gbp(1050)]);
+        assert!(series.series[1].1[0].is_empty());
+        assert!(series.series[1].1[2] == gbp(50));
+
+        let mut prices = PriceMap::default();
+        let usd = Amount::new(Decimal::new(125, 2), "USD".to_string());
+        prices.insert(d(2023, 3, 1), "GBP", &usd);
+        series.convert(&prices, "USD");
+        let table = series.table(&ledger.opts);
+        assert!(
+            table.columns
+                == [
+                    "Date",
+                    "Net Worth (GBP)",
+                    "Net Worth (USD)",
+                    "Expenses (GBP)",
+                    "Expenses (USD)"
+                ]
+        );
+        assert!(table.rows[2][2] == Value::Number(Decimal::new(131250, 2)));
+        assert!(table.rows[2][3] == Value::Number(Decimal::ZERO));
+    }
+}
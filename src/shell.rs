@@ -0,0 +1,216 @@
+//! An interactive shell for queries, like beancount's `bean-query`
+//!
+//! The ledger is loaded once, then each line is either a query or one of
+//! the dot-commands listed by `.help`.
+
+use std::process::ExitCode;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::data::Directive;
+use crate::ledger::Ledger;
+use crate::query;
+use crate::realization;
+
+const HELP: &str = "\
+Enter a query, eg: SELECT account, sum(position) GROUP BY account
+Or one of these commands:
+  .explain <query>  Show how a query will be run
+  .tables           List the tables and their columns
+  .functions        List the functions
+  .queries          List the queries declared in the file
+  .run <name>       Run a query declared in the file
+  .errors           Show the errors found when loading
+  .reload           Load the file again, after it has changed
+  .help             Show this message
+  .exit             Quit (or Ctrl-D)";
+
+/// What to do with the result of a line of input
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Print(String),
+    Error(String),
+    Exit,
+}
+
+/// The state of the shell: the file and the Ledger loaded from it
+pub struct Shell {
+    path: String,
+    pub ledger: Ledger,
+}
+
+impl Shell {
+    pub fn new(path: &str) -> Result<Self, String> {
+        let ledger = read(path)?;
+        let path = path.to_string();
+        Ok(Self { path, ledger })
+    }
+
+    /// Load the file again, returning a summary of what was loaded
+    pub fn reload(&mut self) -> Result<String, String> {
+        self.ledger = read(&self.path)?;
+        Ok(format!(
+            "Loaded {path}: {dirs} directives, {errs} errors",
+            path = self.path,
+            dirs = self.ledger.dirs.len(),
+            errs = self.ledger.errs.len(),
+        ))
+    }
+
+    /// Evaluate one line of input
+    pub fn eval(&mut self, line: &str) -> Action {
+        let line = line.trim();
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        let result = match command {
+            "" => Ok(String::new()),
+            ".exit" | ".quit" => return Action::Exit,
+            ".help" => Ok(HELP.to_string()),
+            ".explain" => query::parse(arg)
+                .map(|select| select.explain())
+                .map_err(|e| e.msg),
+            ".tables" => Ok(format!("postings: {}", query::COLUMNS.join(", "))),
+            ".functions" => Ok(functions()),
+            ".queries" => Ok(self.queries()),
+            ".run" => query::run_named(&self.ledger, arg)
+                .map(|table| table.render())
+                .map_err(|e| e.msg),
+            ".errors" => Ok(self
+                .ledger
+                .errs
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")),
+            ".reload" => self.reload(),
+            _ if command.starts_with('.') => Err(format!("Unknown command: {command}, try .help")),
+            _ => query::run(&self.ledger, line)
+                .map(|table| table.render())
+                .map_err(|e| e.msg),
+        };
+        match result {
+            Ok(out) => Action::Print(out),
+            Err(err) => Action::Error(err),
+        }
+    }
+
+    fn queries(&self) -> String {
+        let mut rows = vec![["Name".to_string(), "Query".to_string()]];
+        for d in &self.ledger.dirs {
+            if let Directive::Query(q) = d {
+                rows.push([q.name.clone(), q.query.clone()]);
+            }
+        }
+        realization::format_columns(&rows, 2)
+    }
+}
+
+/// Load and balance the file, without panicking if it can't be read
+fn read(path: &str) -> Result<Ledger, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {path}: {e}"))?;
+    let (ledger, _) = crate::balance_ledger(crate::load(text));
+    Ok(ledger)
+}
+
+fn functions() -> String {
+    let functions = query::FUNCTIONS
+        .iter()
+        .map(|(name, args)| format!("{name}/{args}"));
+    let aggregates = query::AGGREGATES.iter().map(|name| format!("{name}/1"));
+    let all: Vec<String> = functions.chain(aggregates).collect();
+    all.join(", ")
+}
+
+/// The file that history is kept in between sessions, if there is a home directory
+fn history_path() -> Option<std::path::PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(std::path::Path::new(&home).join(".bean-rs_history"))
+}
+
+/// Run the shell on the file at path until the user quits
+pub fn run(path: &str) -> ExitCode {
+    let mut shell = match Shell::new(path) {
+        Ok(shell) => shell,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Cannot start the shell: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let history = history_path();
+    if let Some(history) = &history {
+        // There is no history the first time
+        let _ = editor.load_history(history);
+    }
+    if let Action::Print(out) = shell.eval(".errors") {
+        if !out.is_empty() {
+            eprintln!("-- Errors -- \n{out}");
+        }
+    }
+    println!("Type .help for help, .exit to quit");
+    loop {
+        match editor.readline("beancount> ") {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                }
+                match shell.eval(&line) {
+                    Action::Print(out) if out.is_empty() => (),
+                    Action::Print(out) => println!("{}", out.trim_end()),
+                    Action::Error(err) => eprintln!("{err}"),
+                    Action::Exit => break,
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(action: Action) -> String {
+        match action {
+            Action::Print(out) => out,
+            action => panic!("Expected output, got {action:?}"),
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut shell = Shell::new("example.bean").unwrap();
+        let out = output(shell.eval("SELECT account WHERE number > 0 LIMIT 1"));
+        assert!(out == "account\nAssets:Bank\n");
+        assert!(output(shell.eval(".tables")).starts_with("postings: date, year"));
+        assert!(output(shell.eval(".queries")).contains("food"));
+        assert!(output(shell.eval(".run food")).lines().count() == 3);
+        assert!(output(shell.eval(".explain BALANCES")).contains("GROUP BY account"));
+        assert!(output(shell.eval(".reload")).starts_with("Loaded example.bean"));
+        assert!(output(shell.eval("")).is_empty());
+
+        assert!(matches!(shell.eval("SELECT nope"), Action::Error(_)));
+        assert!(matches!(shell.eval(".run nope"), Action::Error(_)));
+        assert!(matches!(shell.eval(".nope"), Action::Error(_)));
+        assert!(shell.eval(".exit") == Action::Exit);
+        assert!(Shell::new("doesntexist.bean").is_err());
+    }
+}
@@ -1,9 +1,10 @@
 use log::debug;
 use pest::iterators::Pair;
 
-use crate::data::{AccBal, Directive};
+use crate::data::{AccBal, Directive, Options};
 use crate::error::BeanError;
 use crate::grammar::Rule;
+use crate::realization::{RealAccount, TreeOptions};
 
 pub fn debug_directives(directives: &Vec<Directive>) {
     for d in directives {
@@ -17,13 +18,20 @@ pub fn print_directives(directives: &Vec<Directive>) {
     }
 }
 
-pub fn print_bals(bals: AccBal) {
+/// Balances as an indented account tree
+pub fn print_bals(bals: AccBal, tree: &TreeOptions, opts: &Options) {
     println!("-- Balances --");
-    for (acc, ccy_bals) in bals {
-        for (ccy, amount) in ccy_bals {
-            println!("{acc} {amount} {ccy}");
-        }
-    }
+    print!("{}", RealAccount::realize(&bals).render(tree, opts));
+}
+
+/// Balances for one section, titled with its heading path
+pub fn print_section_bals(section: &[String], bals: AccBal, tree: &TreeOptions, opts: &Options) {
+    let title = match section.is_empty() {
+        true => "(no section)".to_string(),
+        false => section.join(" > "),
+    };
+    println!("-- Balances: {title} --");
+    print!("{}", RealAccount::realize(&bals).render(tree, opts));
 }
 
 pub fn print_errors(errs: &Vec<BeanError>) {
@@ -35,6 +43,40 @@ pub fn print_errors(errs: &Vec<BeanError>) {
     }
 }
 
+/// Resolve the backslash escapes in the contents of a string literal
+pub fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Write a string as a quoted literal, escaping as needed
+/// Newlines are left as they are, as multi-line strings are allowed
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
 pub fn debug_pair(pair: &Pair<Rule>, depth: usize) {
     if depth == 0 {
         debug!("full parse output");
@@ -60,13 +102,13 @@ pub fn debug_pair(pair: &Pair<Rule>, depth: usize) {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     use chrono::NaiveDate;
     use rust_decimal::Decimal;
 
     use crate::{
-        data::{CcyBal, Commodity, DebugLine},
+        data::{CcyBal, Comments, Commodity, DebugLine},
         error::ErrorType,
     };
 
@@ -78,6 +120,8 @@ mod tests {
             date: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
             ccy: "USD".to_string(),
             meta: vec![],
+            comments: Comments::default(),
+            section: vec![],
             debug: DebugLine { line: 0 },
         };
         let vec = vec![Directive::Commodity(comm)];
@@ -86,11 +130,18 @@ mod tests {
 
     #[test]
     fn test_print_bals() {
-        let mut bals: AccBal = HashMap::new();
-        let mut ccybal: CcyBal = HashMap::new();
+        let mut bals: AccBal = BTreeMap::new();
+        let mut ccybal: CcyBal = BTreeMap::new();
         ccybal.insert("USD".to_string(), Decimal::new(100, 1));
         bals.insert("Assets:Checking".to_string(), ccybal);
-        print_bals(bals);
+        print_bals(bals, &TreeOptions::default(), &Options::default());
+    }
+
+    #[test]
+    fn test_escape() {
+        let text = unescape(r#"Dinner at \"Joe's\" \\ \n"#);
+        assert!(text == "Dinner at \"Joe's\" \\ \n");
+        assert!(escape(&text) == "\"Dinner at \\\"Joe's\\\" \\\\ \n\"");
     }
 
     #[test]
@@ -99,6 +150,8 @@ mod tests {
             date: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
             ccy: "USD".to_string(),
             meta: vec![],
+            comments: Comments::default(),
+            section: vec![],
             debug: DebugLine { line: 0 },
         };
         let err = BeanError::new(
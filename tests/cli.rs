@@ -21,3 +21,273 @@ fn run_check() {
     cmd.arg("check").arg("example.bean");
     cmd.assert().success();
 }
+
+#[test]
+fn run_format() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("format").arg("example.bean");
+    cmd.assert().success();
+}
+
+#[test]
+fn run_format_check() {
+    // example.bean isn't aligned to the default column
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("format").arg("--check").arg("example.bean");
+    cmd.assert().failure();
+}
+
+#[test]
+fn run_balance_section() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("balance")
+        .arg("--section")
+        .arg("Transactions")
+        .arg("example.bean");
+    cmd.assert().success();
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("balance").arg("--by-section").arg("example.bean");
+    cmd.assert().success();
+}
+
+#[test]
+fn run_balance_tree() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("balance")
+        .arg("--depth")
+        .arg("1")
+        .arg("--hide-zero")
+        .arg("example.bean");
+    cmd.assert().success();
+}
+
+#[test]
+fn run_balance_is_stable() {
+    let run = || {
+        let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+        cmd.arg("balance").arg("example.bean");
+        String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+    };
+    let out = run();
+    assert_eq!(out, run());
+    let roots: Vec<&str> = out
+        .lines()
+        .filter(|l| l.starts_with(char::is_uppercase) && !l.starts_with("Account"))
+        .map(|l| l.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(roots, ["Assets", "Equity", "Income", "Expenses"]);
+}
+
+#[test]
+fn run_reports() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("bs")
+        .arg("--end")
+        .arg("2023-03-01")
+        .arg("example.bean");
+    let out = cmd.output().unwrap();
+    assert!(out.status.success());
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(out.contains("Conversions"));
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("is")
+        .arg("--begin")
+        .arg("2023-01-01")
+        .arg("--depth")
+        .arg("1")
+        .arg("example.bean");
+    cmd.assert().success();
+}
+
+#[test]
+fn run_trial_balance() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("trial-balance").arg("tests/data/trial.bean");
+    cmd.assert().success();
+    // The "Shop" transaction in example.bean converts GBP to USD
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("trial-balance").arg("example.bean");
+    cmd.assert().success();
+}
+
+#[test]
+fn run_register() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("register")
+        .arg("example.bean")
+        .arg("Assets")
+        .arg("--subaccounts")
+        .arg("--others");
+    cmd.assert().success();
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("register").arg("example.bean").arg("Assets:(");
+    cmd.assert().failure();
+}
+
+#[test]
+fn run_periods() {
+    for args in [
+        vec!["balance", "--as-of", "2023-02-02"],
+        vec!["bs", "--begin", "2023-02-03", "--end", "2023-03-01"],
+        vec!["is", "--begin", "2023-02-02", "--as-of", "2023-02-28"],
+        vec!["bs", "--period", "2023-Q1", "--close"],
+        vec!["bs", "--as-of", "2023-03-31", "--unrealized", "--close"],
+        vec!["is", "--period", "2023-02..2023-03"],
+        vec![
+            "register",
+            "--begin",
+            "2023-02-02",
+            "example.bean",
+            "Assets:Bank",
+        ],
+    ] {
+        let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+        cmd.args(&args);
+        if args[0] != "register" {
+            cmd.arg("example.bean");
+        }
+        cmd.assert().success();
+    }
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.args(["balance", "--end", "2023-01-01", "--as-of", "2023-01-01"]);
+    cmd.arg("example.bean");
+    cmd.assert().failure();
+
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.args(["bs", "--period", "2023", "--close", "example.bean"]);
+    let out = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(out.contains("Current"));
+
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.args(["bs", "--period", "2023", "--begin", "2023-01-01"]);
+    cmd.arg("example.bean");
+    cmd.assert().failure();
+}
+
+#[test]
+fn run_query() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("query")
+        .arg("tests/data/trial.bean")
+        .arg("SELECT account, sum(position) GROUP BY account ORDER BY account");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("query")
+        .arg("example.bean")
+        .arg("--run")
+        .arg("food");
+    let out = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert_eq!(out.lines().count(), 3);
+
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("query").arg("example.bean").arg("SELECT nope");
+    cmd.assert().failure();
+
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("query")
+        .arg("example.bean")
+        .arg("--run")
+        .arg("missing");
+    cmd.assert().failure();
+}
+
+#[test]
+fn run_filter() {
+    let run = |filter: &str| {
+        let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+        cmd.arg("balance")
+            .arg("--filter")
+            .arg(filter)
+            .arg("example.bean");
+        let out = cmd.output().unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let (ok, out) = run("#tag date:2023-02");
+    assert!(ok);
+    assert!(out.contains("-40.00 GBP") && !out.contains("Income"));
+    let (ok, _) = run("date:nope");
+    assert!(!ok);
+}
+
+#[test]
+fn run_balance_interval() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("balance")
+        .arg("--interval")
+        .arg("month")
+        .arg("--output")
+        .arg("csv")
+        .arg("example.bean");
+    let out = cmd.output().unwrap();
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(out.starts_with("Account,Currency,2023-02,2023-03,Total,Average\n"));
+    assert!(out.contains("Income:Job,GBP,1000,0,1000,500\n"));
+
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("balance").arg("--cumulative").arg("example.bean");
+    cmd.assert().failure();
+}
+
+#[test]
+fn run_series() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("series")
+        .arg("example.bean")
+        .arg("Expenses")
+        .arg("--output")
+        .arg("csv");
+    let out = cmd.output().unwrap();
+    assert!(out.status.success());
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(out.starts_with("Date,Net Worth (GBP),Net Worth (GOO),Expenses (GBP),Expenses (USD)\n"));
+    assert!(out.contains("2023-03-31,860.00,111,100,40.00\n"));
+}
+
+#[test]
+fn run_cashflow() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("cashflow")
+        .arg("--cash")
+        .arg("Assets:Bank")
+        .arg("example.bean");
+    let out = cmd.output().unwrap();
+    assert!(out.status.success());
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(out.contains("Net Cash from Operating"));
+    assert!(out.contains("Closing Cash"));
+}
+
+#[test]
+fn run_holdings() {
+    let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+    cmd.arg("holdings")
+        .arg("example.bean")
+        .arg("--output")
+        .arg("csv");
+    let out = cmd.output().unwrap();
+    assert!(out.status.success());
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(out.starts_with("Account,Units,Average Cost,Book Value,Price,Market Value"));
+    assert!(out.contains("Assets:Bank,860.00 GBP,1 GBP,860.00 GBP,1 GBP,860.00 GBP"));
+    assert!(out.contains("Assets:Invest,111 GOO,,,,,,,\n"));
+}
+
+#[test]
+fn run_unrealized() {
+    for begin in [None, Some("2023-03-01")] {
+        let mut cmd = Command::cargo_bin("bean-rs").unwrap();
+        cmd.args(["bs", "--unrealized"]);
+        if let Some(begin) = begin {
+            cmd.args(["--begin", begin]);
+        }
+        let out = cmd.arg("tests/data/holdings.bean").output().unwrap();
+        assert!(out.status.success());
+        let out = String::from_utf8(out.stdout).unwrap();
+        // Only the holding at cost has a gain booked, the one bought at a price has not
+        let lines: Vec<&str> = out.lines().filter(|l| l.contains("Unrealized")).collect();
+        assert!(lines.len() == 1, "{out}");
+        let words: Vec<&str> = lines[0].split_whitespace().collect();
+        assert!(words == ["Unrealized", "350", "GBP", "350", "GBP"], "{out}");
+    }
+}
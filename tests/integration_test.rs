@@ -1,8 +1,9 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use bean_rs::data::AccBal;
 use bean_rs::ledger::Ledger;
 use bean_rs::{balance, load};
+use regex::Regex;
 use rust_decimal::Decimal;
 
 #[test]
@@ -20,26 +21,26 @@ fn test_load() {
 #[test]
 fn test_balance() {
     let (bals, _) = balance("example.bean");
-    let want: AccBal = HashMap::from([
+    let want: AccBal = BTreeMap::from([
         (
             "Assets:Invest".to_string(),
-            HashMap::from([("GOO".to_string(), Decimal::new(111, 0))]),
+            BTreeMap::from([("GOO".to_string(), Decimal::new(111, 0))]),
         ),
         (
             "Income:Job".to_string(),
-            HashMap::from([("GBP".to_string(), Decimal::new(-1000, 0))]),
+            BTreeMap::from([("GBP".to_string(), Decimal::new(-1000, 0))]),
         ),
         (
             "Equity:Bals".to_string(),
-            HashMap::from([("GOO".to_string(), Decimal::new(-111, 0))]),
+            BTreeMap::from([("GOO".to_string(), Decimal::new(-111, 0))]),
         ),
         (
             "Assets:Bank".to_string(),
-            HashMap::from([("GBP".to_string(), Decimal::new(86000, 2))]),
+            BTreeMap::from([("GBP".to_string(), Decimal::new(86000, 2))]),
         ),
         (
             "Expenses:Food".to_string(),
-            HashMap::from([
+            BTreeMap::from([
                 ("USD".to_string(), Decimal::new(4000, 2)),
                 ("GBP".to_string(), Decimal::new(100, 0)),
             ]),
@@ -47,3 +48,74 @@ fn test_balance() {
     ]);
     assert!(bals.eq(&want));
 }
+
+/// Every field of the Ledger's Directives and Options, compared exactly
+/// rather than with the tolerance of `Amount`'s `PartialEq`
+/// The line numbers are left out unless `lines` is set
+fn strict(ledger: &Ledger, lines: bool) -> String {
+    let text = format!("{:#?}\n{:#?}", ledger.dirs, ledger.opts);
+    match lines {
+        true => text,
+        false => Regex::new(r"line: \d+")
+            .unwrap()
+            .replace_all(&text, "line: _")
+            .to_string(),
+    }
+}
+
+/// Load, print and load again, checking that nothing was lost along the way
+fn round_trip(path: &str) {
+    let text = std::fs::read_to_string(path).expect("cannot read file");
+    let ledger = load(text);
+    assert!(ledger.errs.is_empty(), "{:?}", ledger.errs);
+    let printed = bean_rs::printer::print_ledger(&ledger);
+    let reloaded = load(printed.clone());
+    assert!(reloaded.errs.is_empty(), "{:?}", reloaded.errs);
+    assert_eq!(
+        strict(&ledger, false),
+        strict(&reloaded, false),
+        "\n{printed}"
+    );
+    // And printing is stable after the first round
+    assert_eq!(printed, bean_rs::printer::print_ledger(&reloaded));
+}
+
+#[test]
+fn test_round_trip_example() {
+    round_trip("example.bean");
+}
+
+#[test]
+fn test_round_trip_corpus() {
+    round_trip("tests/data/corpus.bean");
+}
+
+#[test]
+fn test_format_keeps_ledger() {
+    let text = std::fs::read_to_string("tests/data/corpus.bean").expect("cannot read file");
+    let opts = bean_rs::format::FormatOptions::default();
+    let formatted = bean_rs::format::format(&text, &opts);
+    assert_ne!(text, formatted);
+    // Formatting never moves a line, so even the line numbers are the same
+    assert_eq!(strict(&load(text), true), strict(&load(formatted), true));
+}
+
+#[test]
+fn test_cst_lossless() {
+    for path in ["example.bean", "tests/data/corpus.bean"] {
+        let text = std::fs::read_to_string(path).expect("cannot read file");
+        let cst = bean_rs::cst::parse(&text);
+        assert_eq!(cst.to_string(), text);
+    }
+}
+
+#[test]
+fn test_load_period_errors() {
+    let (ledger, _) = bean_rs::load_balanced("tests/data/closed.bean");
+    assert!(ledger.errs.len() == 1);
+    // The opening balance for the closed account is a new error, the assertion is not
+    let begin = chrono::NaiveDate::from_ymd_opt(2023, 2, 1);
+    let (ledger, _) = bean_rs::load_period("tests/data/closed.bean", begin, None);
+    assert!(ledger.errs.len() == 2, "{:?}", ledger.errs);
+    assert!(ledger.errs[1].ty == bean_rs::error::ErrorType::ClosedAccount);
+}